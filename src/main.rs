@@ -2,16 +2,36 @@ use clap::Parser;
 use std::{
     env::home_dir,
     fs::File,
-    io::Read,
+    io::{Read, Stdout},
     path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
 };
 
 mod rtail;
-use rtail::{Args, FollowFile, offset_tail, tail_bytes, tail_file};
+use rtail::{
+    Args, FollowFile, MERGE_WINDOW, MergeRecord, Merger, Printer, ProcessWatch, StopConditions,
+    StopReason, Timeouts, WatchedProcess, file_labels, find_byte_offset, find_line_offset,
+    follow_merged, is_broken_pipe, offset_tail, resolve_pids, tail_byte_range, tail_bytes,
+    tail_chars, tail_file, tail_from, tail_line_range, tail_records, tail_reverse, tail_time_range,
+    write_header,
+};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() {
     let args: Args = Args::parse();
 
+    match run(args) {
+        Ok(()) => {}
+        // Like GNU tail, stop quietly once the reader has gone away
+        Err(e) if is_broken_pipe(&*e) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     // Collect inputfiles, expanding ~ to home directory if needed
     let input_files = args
         .filename
@@ -19,26 +39,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap_or(vec!["stdin".to_string()])
         .into_iter()
         .map(|in_file| {
-            if in_file.starts_with('~') {
-                if let Some(home_path) = home_dir() {
-                    return in_file.replacen('~', &home_path.to_string_lossy(), 1);
-                }
+            if in_file.starts_with('~')
+                && let Some(home_path) = home_dir()
+            {
+                return in_file.replacen('~', &home_path.to_string_lossy(), 1);
             }
             in_file
         })
         .collect::<Vec<String>>();
 
-    // Parse num_lines argument
+    // Parse num_lines argument, -r prints every line by default
+    let lines_arg: &str = match &args.num_lines {
+        Some(lines) => lines,
+        None if args.reverse => "+1",
+        None => "10",
+    };
     let mut is_plus_lines: bool = false;
-    let num_lines: u64 = if args.num_lines.starts_with('+') {
-        let n = args.num_lines[1..].parse::<u64>()?;
+    let num_lines: u64 = if let Some(plus_lines) = lines_arg.strip_prefix('+') {
+        let n = plus_lines.parse::<u64>()?;
         is_plus_lines = true;
         n
     } else {
-        match args.num_lines.parse::<u64>() {
+        match lines_arg.parse::<u64>() {
             Ok(n) => n,
             Err(e) => {
-                eprintln!("Error parsing number of lines '{}': {}", args.num_lines, e);
+                eprintln!("Error parsing number of lines '{}': {}", lines_arg, e);
                 return Ok(());
             }
         }
@@ -62,8 +87,46 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         None => None,
     };
 
+    // Parse chars argument if provided
+    let mut is_plus_chars: bool = false;
+    let num_chars: Option<u64> = match &args.chars {
+        Some(char_str) => {
+            if char_str.starts_with('+') {
+                is_plus_chars = true;
+            }
+            match char_str.trim_start_matches('+').parse::<u64>() {
+                Ok(n) => Some(n),
+                Err(e) => {
+                    eprintln!("Error parsing number of characters '{}': {}", char_str, e);
+                    return Ok(());
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut printer = Printer::new(&args)?;
+
+    // With --merge, records are collected to be written in time order
+    let merge_channel = args.merge.then(mpsc::channel::<MergeRecord>);
+    let mut merger: Option<Merger<Stdout>> = args.merge.then(|| {
+        Merger::new(
+            std::io::stdout(),
+            StopConditions::new(args.until.clone(), args.max_lines),
+        )
+    });
+    if let Some((merge_tx, _)) = &merge_channel {
+        printer.set_merge(merge_tx.clone());
+    }
+
+    // The label of each file with --prefix, in the order of input_files
+    let labels: Vec<Vec<u8>> = match args.prefix_mode() {
+        Some(mode) => file_labels(&input_files, mode, &args.label, args.color.enabled())?,
+        None => vec![Vec::new(); input_files.len()],
+    };
+
     // Process each input file
-    for input_file in input_files.clone() {
+    for (input_file, label) in input_files.clone().into_iter().zip(&labels) {
         // Open the file
         let mut file: File = if input_file == "stdin" {
             // Read from stdin
@@ -85,59 +148,207 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         };
 
-        // Print header if multiple files or verbose
-        if (input_files.len() > 1 && input_file != "stdin" || args.verbose) && !args.quiet {
+        printer.start_file(&file, &input_file)?;
+        printer.set_label(label);
+
+        // Print header if multiple files or verbose, unless each record is labelled
+        if (input_files.len() > 1 && input_file != "stdin" || args.verbose)
+            && !args.quiet
+            && args.prefix_mode().is_none()
+        {
             let pre_new_line: &str = if input_file == input_files[0] {
                 ""
             } else {
                 "\n"
             };
-            println!("{}==> {} <==", pre_new_line, input_file);
+            printer.notice(&format!("{}==> {} <==", pre_new_line, input_file))?;
         }
 
+        // Print the header lines ahead of the tail
+        write_header(&mut file, &mut printer)?;
+
         // Call appropriate tail function
-        match num_bytes {
-            Some(n) => {
-                // Tail by bytes
-                tail_bytes(&mut file, n, is_plus_bytes)?;
-            }
-            None => {
-                if is_plus_lines {
-                    // Tail by offset from line N
-                    offset_tail(&mut file, num_lines, args.zero_terminated)?;
-                } else {
-                    // Tail by last N lines
-                    tail_file(&mut file, num_lines, args.zero_terminated)?;
+        if let Some(range) = args.range {
+            // Print a range of lines
+            tail_line_range(&mut file, range, &mut printer)?;
+        } else if let Some(range) = args.byte_range {
+            // Print a range of bytes
+            tail_byte_range(&mut file, range, &mut printer)?;
+        } else if let Some(start) = args.from {
+            // Print from an offset or a percentage of the file
+            tail_from(&mut file, start, &mut printer)?;
+        } else if args.since.is_some() || args.until_time.is_some() {
+            // Print the records stamped within a time range
+            tail_time_range(&mut file, args.since, args.until_time, &mut printer)?;
+        } else if args.reverse {
+            let (start_offset, count) = match num_bytes {
+                Some(n) => (
+                    find_byte_offset(&file, n, is_plus_bytes, args.align_utf8, &printer)?,
+                    None,
+                ),
+                None if is_plus_lines => (find_line_offset(&file, num_lines, &printer)?, None),
+                None => (0, Some(num_lines)),
+            };
+            // Print lines newest first
+            tail_reverse(&mut file, start_offset, count, &mut printer)?;
+        } else {
+            match (num_bytes, num_chars) {
+                (Some(n), _) => {
+                    // Tail by bytes
+                    tail_bytes(&mut file, n, is_plus_bytes, args.align_utf8, &mut printer)?;
+                }
+                (None, Some(n)) => {
+                    // Tail by characters or grapheme clusters
+                    tail_chars(&mut file, n, is_plus_chars, args.graphemes, &mut printer)?;
+                }
+                (None, None) => {
+                    if let Some(record_size) = args.record_size {
+                        // Tail by fixed-size records
+                        tail_records(
+                            &mut file,
+                            num_lines,
+                            is_plus_lines,
+                            record_size,
+                            &mut printer,
+                        )?;
+                    } else if is_plus_lines {
+                        // Tail by offset from line N
+                        offset_tail(&mut file, num_lines, &mut printer)?;
+                    } else {
+                        // Tail by last N lines
+                        tail_file(&mut file, num_lines, &mut printer)?;
+                    }
                 }
             }
         }
+
+        // A stop condition was met, so there is nothing more to output
+        if printer.stop_reason().is_some() {
+            break;
+        }
+    }
+
+    if let (Some(merger), Some((_, merge_rx))) = (&mut merger, &merge_channel) {
+        merger.write_sorted(merge_rx.try_iter().collect())?;
+    }
+
+    let stop_reason = printer
+        .stop_reason()
+        .or(merger.as_ref().and_then(|merger| merger.stop_reason()));
+    if let Some(reason) = stop_reason {
+        report_unparsed(&printer, &args);
+        std::process::exit(reason.exit_code());
     }
 
     // Handle follow option
     if args.follow || args.follow_name {
-        // Only follow a single file
-        if input_files.len() > 1 {
+        // Only follow a single file, unless each record is labelled with its file
+        if input_files.len() > 1 && args.prefix_mode().is_none() {
             println!();
-            eprintln!("Error: --follow option can only be used with a single file.");
+            eprintln!(
+                "Error: --follow option can only be used with a single file, or with --prefix or --merge."
+            );
             std::process::exit(1);
-        } else if input_files[0] == "stdin" {
+        } else if input_files.iter().any(|input_file| input_file == "stdin") {
             println!();
             eprintln!("Error: --follow option cannot be used with stdin.");
             std::process::exit(1);
         }
+        // Resolve the processes whose death ends the follow
+        let processes: Vec<WatchedProcess> =
+            match resolve_pids(&args.terminate_after_pid, &args.pid_file, &args.pid_name) {
+                Ok(processes) => processes,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+        let process_watch: Option<ProcessWatch> =
+            (!processes.is_empty()).then(|| ProcessWatch::new(processes, args.pid_exit));
+        // Shared by every file followed, so that one quiet file doesn't count as idle
+        let timeouts = Timeouts::new(args.timeout, args.idle_timeout);
+
+        if input_files.len() > 1 || args.merge {
+            // Each file gets its own printer, which sends its records to be written
+            // in time order with --merge, or as they arrive otherwise; the tail
+            // printer sends to the merge channel too, so never follows on its own
+            let (merge_tx, merge_rx) = merge_channel.unwrap_or_else(mpsc::channel);
+            let window = if args.merge {
+                MERGE_WINDOW
+            } else {
+                Duration::ZERO
+            };
+            // Carry on counting --max-lines from the output so far
+            let merger = merger
+                .unwrap_or_else(|| Merger::new(std::io::stdout(), printer.take_stop_conditions()));
+            drop(printer);
+
+            let mut followers: Vec<FollowFile> = Vec::new();
+            for (idx, (input_file, label)) in input_files.iter().zip(&labels).enumerate() {
+                let follow_full_path: PathBuf = Path::new(input_file).canonicalize()?;
+                let mut file_printer = Printer::new(&args)?;
+                file_printer.set_merge(merge_tx.clone());
+                file_printer.resume_file(&File::open(&follow_full_path)?, input_file)?;
+                file_printer.set_label(label);
+                if idx > 0 {
+                    file_printer.skip_periodic_marks();
+                }
+
+                followers.push(FollowFile::new(
+                    &follow_full_path,
+                    args.follow_name,
+                    process_watch
+                        .as_ref()
+                        .map(ProcessWatch::try_clone)
+                        .transpose()?,
+                    timeouts.clone(),
+                    file_printer,
+                )?);
+            }
+            drop(merge_tx);
+
+            let reason: StopReason = follow_merged(followers, merge_rx, merger, window)?;
+            std::process::exit(reason.exit_code());
+        }
+
         // Follow the specified file
         let follow_file_name: String = input_files[0].clone();
         let follow_full_path: PathBuf = Path::new(&follow_file_name).canonicalize()?;
 
         let mut follower = FollowFile::new(
             &follow_full_path,
-            args.zero_terminated,
             args.follow_name,
-            args.terminate_after_pid,
+            process_watch,
+            timeouts,
+            printer,
         )?;
 
-        follower.follow_file_inotify()?;
+        let reason: StopReason = follower.follow_file_inotify()?;
+        report_unparsed(&follower.printer, &args);
+        std::process::exit(reason.exit_code());
+    }
+
+    report_unparsed(&printer, &args);
+
+    // Without -f there is no more data to come, so --until has failed
+    if args.until.is_some() {
+        std::process::exit(1);
     }
 
     Ok(())
 }
+
+/// Say how many lines --parse couldn't read, on stderr so the output stays clean
+fn report_unparsed(printer: &Printer, args: &Args) {
+    if let Some(format) = &args.parse
+        && printer.unparsed_count() > 0
+    {
+        let count = printer.unparsed_count();
+        eprintln!(
+            "rtail: {} line{} could not be parsed as {}",
+            count,
+            if count == 1 { "" } else { "s" },
+            format.name()
+        );
+    }
+}
@@ -1,6 +1,16 @@
-use std::env;
+use regex::bytes::Regex;
+use std::{env, path::PathBuf, time::Duration};
 
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use clap::{ArgGroup, Parser};
+use encoding_rs::Encoding;
+
+use crate::rtail::{
+    ColorMode, Delimiter, Expr, FieldList, Label, LineFormat, LongLinePolicy, OutputFormat,
+    PidExitMode, PrefixMode, Range, StartPoint, Template, TimestampFormat, parse_duration,
+    parse_encoding, parse_separator, parse_time, parse_time_format, parse_zone,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
@@ -14,27 +24,152 @@ const EXAMPLES: &str =
     override_usage = format!("{}{}", USAGE, EXAMPLES), group(
         ArgGroup::new("follow_mode")
             .args(["follow", "follow_name"])
+    ), group(
+        ArgGroup::new("structured")
+            .args(["json", "parse"])
+            .multiple(true)
     ))]
 pub struct Args {
     pub filename: Option<Vec<String>>,
 
-    /// Output the last NUM lines;
+    /// Output the last NUM lines, 10 by default or all of them with -r;
     /// or use -n +NUM to output starting with line NUM of each file
-    #[arg(short, long = "lines", default_value_t = String::from("10"))]
-    pub num_lines: String,
+    #[arg(short, long = "lines", value_name = "NUM")]
+    pub num_lines: Option<String>,
+
+    /// Output lines in reverse order, newest first
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["follow_mode", "chars", "record_size", "record_start"]
+    )]
+    pub reverse: bool,
 
     /// Output the last NUM bytes;
     /// or use -c +NUM to output starting with byte NUM of each file
     #[arg(short = 'c', long)]
     pub bytes: Option<String>,
 
+    /// Use with -c, move the start forward to the next UTF-8 character boundary
+    #[arg(long, default_value_t = false, requires = "bytes")]
+    pub align_utf8: bool,
+
+    /// Output the last NUM characters;
+    /// or use --chars +NUM to output starting with character NUM of each file
+    #[arg(long, value_name = "NUM", conflicts_with_all = ["bytes", "record_size"])]
+    pub chars: Option<String>,
+
+    /// Use with --chars, count grapheme clusters instead of Unicode scalar values
+    #[arg(long, default_value_t = false, requires = "chars")]
+    pub graphemes: bool,
+
+    /// Output lines START to END, such as 1000:2000 or -50:-10;
+    /// negative positions count back from the last line
+    #[arg(
+        long,
+        value_name = "START:END",
+        value_parser = Range::parse,
+        allow_hyphen_values = true,
+        conflicts_with_all = ["num_lines", "bytes", "chars", "reverse", "record_size", "follow_mode"]
+    )]
+    pub range: Option<Range>,
+
+    /// Output bytes START to END, counting from 1;
+    /// negative positions count back from the last byte
+    #[arg(
+        long,
+        value_name = "START:END",
+        value_parser = Range::parse,
+        allow_hyphen_values = true,
+        conflicts_with_all = ["num_lines", "bytes", "chars", "reverse", "range", "follow_mode"]
+    )]
+    pub byte_range: Option<Range>,
+
+    /// Output starting at byte OFFSET or at PERCENT% of the file,
+    /// moved forward to the start of the next line
+    #[arg(
+        long,
+        value_name = "OFFSET|PERCENT%",
+        value_parser = StartPoint::parse,
+        conflicts_with_all = ["num_lines", "bytes", "chars", "reverse", "range", "byte_range"]
+    )]
+    pub from: Option<StartPoint>,
+
+    /// Interleave the files in the order of the timestamps in their records,
+    /// labelling each record with its file
+    #[arg(long, default_value_t = false, conflicts_with_all = ["record_size", "reverse"])]
+    pub merge: bool,
+
+    /// Label each record with its file; names are the file name (the default),
+    /// the path as given, or only the --label names
+    #[arg(long, value_enum, value_name = "NAMES", num_args = 0..=1, require_equals = true, default_missing_value = "basename", conflicts_with = "record_size")]
+    pub prefix: Option<PrefixMode>,
+
+    /// Label the records of file PATH as NAME, implies --prefix
+    #[arg(long, value_name = "PATH=NAME", value_parser = Label::parse, conflicts_with = "record_size")]
+    pub label: Vec<Label>,
+
+    /// Output the records stamped at or after TIME, such as 15m (ago), 14:05,
+    /// '2024-05-01 14:05:00' or @EPOCH; timestamps are found in each line
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = parse_time,
+        conflicts_with_all = ["num_lines", "bytes", "chars", "reverse", "range", "byte_range", "from", "record_size"]
+    )]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Output the records stamped before TIME, in the same forms as --since
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = parse_time,
+        conflicts_with_all = ["num_lines", "bytes", "chars", "reverse", "range", "byte_range", "from", "record_size", "follow_mode"]
+    )]
+    pub until_time: Option<DateTime<Utc>>,
+
     /// Follow the file for new lines
     #[arg(short, long, default_value_t = false)]
     pub follow: bool,
 
-    /// Use with -f, terminate after process ID, PID dies
-    #[arg(long = "pid", requires = "follow_mode")]
-    pub terminate_after_pid: Option<i32>,
+    /// Use with -f, terminate after process ID, PID dies;
+    /// may be repeated or given as a comma-separated list
+    #[arg(
+        long = "pid",
+        value_name = "PID",
+        value_delimiter = ',',
+        requires = "follow_mode"
+    )]
+    pub terminate_after_pid: Vec<i32>,
+
+    /// Use with -f, read a PID to watch from FILE
+    #[arg(long, value_name = "FILE", requires = "follow_mode")]
+    pub pid_file: Vec<PathBuf>,
+
+    /// Use with -f, watch every running process named NAME
+    #[arg(long, value_name = "NAME", requires = "follow_mode")]
+    pub pid_name: Vec<String>,
+
+    /// Use with -f, with several watched processes exit when all of them or any one has died
+    #[arg(long, value_enum, default_value_t = PidExitMode::All, requires = "follow_mode")]
+    pub pid_exit: PidExitMode,
+
+    /// Exit with status 0 once a line matching REGEX has been output
+    #[arg(long, value_name = "REGEX")]
+    pub until: Option<Regex>,
+
+    /// Use with -f, exit with status 124 once DURATION has passed (e.g. 60s, 5m, 1h30m)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "follow_mode")]
+    pub timeout: Option<Duration>,
+
+    /// Use with -f, exit with status 4 if no new data arrives for DURATION
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "follow_mode")]
+    pub idle_timeout: Option<Duration>,
+
+    /// Exit with status 3 once NUM lines have been output
+    #[arg(long, value_name = "NUM", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_lines: Option<u64>,
 
     /// Follow by file name (handle log rotation)
     #[arg(long, default_value_t = false)]
@@ -44,6 +179,14 @@ pub struct Args {
     #[arg(short = 'z', long, default_value_t = false)]
     pub zero_terminated: bool,
 
+    /// Use STRING as the line delimiter; escapes such as \r\n, \0 and \x1e are allowed
+    #[arg(long, value_name = "STRING", value_parser = Delimiter::parse, conflicts_with = "zero_terminated", allow_hyphen_values = true)]
+    pub delimiter: Option<Delimiter>,
+
+    /// Write STRING after each line instead of the input delimiter
+    #[arg(long, value_name = "STRING", value_parser = Delimiter::parse, allow_hyphen_values = true)]
+    pub output_delimiter: Option<Delimiter>,
+
     /// Always output headers giving file names
     #[arg(short = 'v', long, default_value_t = false)]
     pub verbose: bool,
@@ -51,4 +194,184 @@ pub struct Args {
     /// Do not output headers giving file names
     #[arg(short = 'q', long = "quiet", alias = "silent", default_value_t = false)]
     pub quiet: bool,
+
+    /// Only output lines matching REGEX (may be repeated, any one must match)
+    #[arg(long, value_name = "REGEX")]
+    pub grep: Vec<String>,
+
+    /// Do not output lines matching REGEX (may be repeated)
+    #[arg(long = "grep-v", value_name = "REGEX")]
+    pub grep_v: Vec<String>,
+
+    /// Match --grep and --grep-v patterns case-insensitively
+    #[arg(short = 'i', long, default_value_t = false)]
+    pub ignore_case: bool,
+
+    /// Only output records whose JSON or logfmt fields, or those from --parse, satisfy EXPR,
+    /// such as 'level == "error" && status >= 500 && path ~ "^/api"'
+    #[arg(long = "where", value_name = "EXPR", value_parser = Expr::parse)]
+    pub where_expr: Option<Expr>,
+
+    /// Treat the input as fixed-size binary records of NUM bytes; -n then counts records
+    #[arg(
+        long,
+        value_name = "NUM",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["bytes", "delimiter", "zero_terminated", "record_start", "max_line_length"]
+    )]
+    pub record_size: Option<u64>,
+
+    /// Read the input as ENCODING, such as utf-16le or windows-1252, and write UTF-8;
+    /// detected from a byte order mark when not given
+    #[arg(
+        long,
+        value_name = "ENCODING",
+        value_parser = parse_encoding,
+        conflicts_with = "record_size"
+    )]
+    pub encoding: Option<&'static Encoding>,
+
+    /// Use with --record-size, print each record as a line of hex bytes
+    #[arg(long, default_value_t = false, requires = "record_size")]
+    pub hex: bool,
+
+    /// Treat a line matching REGEX and the lines up to the next match as one record
+    #[arg(long, value_name = "REGEX")]
+    pub record_start: Option<Regex>,
+
+    /// Print the first NUM lines of each file before the tail, and again after a rotation
+    #[arg(long, value_name = "NUM", conflicts_with = "record_size")]
+    pub header_lines: Option<u64>,
+
+    /// Treat the input as CSV with a header line, same as --header-lines 1
+    #[arg(long, default_value_t = false, conflicts_with = "record_size")]
+    pub csv: bool,
+
+    /// Only output fields LIST, such as 1,3,5-7, or by header name, such as ts,status;
+    /// with --json, the keys to keep
+    #[arg(long, value_name = "LIST", value_parser = FieldList::parse)]
+    pub fields: Option<FieldList>,
+
+    /// Split fields on SEP, with CSV quoting, instead of on runs of whitespace;
+    /// --csv implies ','
+    #[arg(long, value_name = "SEP", value_parser = parse_separator)]
+    pub field_separator: Option<u8>,
+
+    /// Pad fields into aligned columns
+    #[arg(long, default_value_t = false)]
+    pub align: bool,
+
+    /// Parse each line as FORMAT: nginx, apache, syslog, logfmt, json,
+    /// or regex:PATTERN with named groups, such as 'regex:(?P<level>\w+) (?P<msg>.*)';
+    /// lines that don't parse are left out and counted
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = LineFormat::parse,
+        conflicts_with = "record_size"
+    )]
+    pub parse: Option<LineFormat>,
+
+    /// Use with --parse, output lines that don't parse as they are
+    #[arg(long, default_value_t = false, requires = "parse")]
+    pub show_unparsed: bool,
+
+    /// Parse each line as JSON, or in the --parse format, and render it as JSON,
+    /// passing other lines through untouched
+    #[arg(long, default_value_t = false, conflicts_with_all = ["csv", "align", "record_size"])]
+    pub json: bool,
+
+    /// Use with --json or --parse, render each record with TEMPLATE,
+    /// such as '{ts} {level:>5} {msg} {*}'
+    #[arg(long, value_name = "TEMPLATE", value_parser = Template::parse, requires = "structured")]
+    pub format: Option<Template>,
+
+    /// Use with --json, print each record indented over several lines
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "json",
+        conflicts_with = "format"
+    )]
+    pub pretty: bool,
+
+    /// Use with --json, remove these keys; may be a comma-separated list
+    #[arg(long, value_name = "KEY", value_delimiter = ',', requires = "json")]
+    pub drop_fields: Vec<String>,
+
+    /// Write each record as a JSON object with its file, inode, offset, line number and
+    /// arrival time, and rotations, truncations and deletions as JSON events
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// When to colour JSON keys, --prefix labels and separators
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorMode::Auto)]
+    pub color: ColorMode,
+
+    /// Rewrite the timestamp of each record in time zone ZONE, such as Europe/Berlin
+    #[arg(long, value_name = "ZONE", value_parser = parse_zone, conflicts_with = "record_size")]
+    pub tz: Option<Tz>,
+
+    /// Rewrite the timestamp of each record in strftime FORMAT, such as '%H:%M:%S%.3f'
+    #[arg(long, value_name = "FORMAT", value_parser = parse_time_format, conflicts_with = "record_size")]
+    pub time_format: Option<String>,
+
+    /// Prefix each record read while following with the time it was read
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "rfc3339", requires = "follow_mode")]
+    pub timestamps: Option<TimestampFormat>,
+
+    /// Prefix each record read while following with the time since the record before it
+    #[arg(long, default_value_t = false, requires = "follow_mode")]
+    pub delta: bool,
+
+    /// Print a separator with the length of the silence before the first record
+    /// read while following after DURATION without any
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "follow_mode")]
+    pub mark_idle: Option<Duration>,
+
+    /// Print a separator with the time every DURATION while following
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "follow_mode")]
+    pub mark_every: Option<Duration>,
+
+    /// Print NUM lines of trailing context after matching lines
+    #[arg(short = 'A', long = "after", value_name = "NUM")]
+    pub after_context: Option<usize>,
+
+    /// Print NUM lines of leading context before matching lines
+    #[arg(short = 'B', long = "before", value_name = "NUM")]
+    pub before_context: Option<usize>,
+
+    /// Print NUM lines of context around matching lines
+    #[arg(short = 'C', long, value_name = "NUM")]
+    pub context: Option<usize>,
+
+    /// Limit each line to NUM bytes, see --long-lines for how longer lines are handled
+    #[arg(long, value_name = "NUM", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_line_length: Option<u64>,
+
+    /// What to do with lines longer than --max-line-length
+    #[arg(long, value_enum, default_value_t = LongLinePolicy::Truncate, requires = "max_line_length")]
+    pub long_lines: LongLinePolicy,
+}
+
+impl Args {
+    /// How records are labelled with their file: --label implies --prefix,
+    /// and --merge labels them with their paths unless told otherwise
+    pub fn prefix_mode(&self) -> Option<PrefixMode> {
+        match self.prefix {
+            Some(mode) => Some(mode),
+            None if !self.label.is_empty() => Some(PrefixMode::Basename),
+            None if self.merge => Some(PrefixMode::Path),
+            None => None,
+        }
+    }
+
+    /// The delimiter between lines, from --delimiter or -z
+    pub fn line_delimiter(&self) -> Delimiter {
+        match &self.delimiter {
+            Some(delimiter) => delimiter.clone(),
+            None if self.zero_terminated => Delimiter::new(b"\0"),
+            None => Delimiter::new(b"\n"),
+        }
+    }
 }
@@ -0,0 +1,76 @@
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use std::time::Instant;
+
+/// How --timestamps writes the time a record was read
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimestampFormat {
+    /// Such as 2024-05-01T14:05:00.123Z
+    Rfc3339,
+    /// Seconds since 1970, such as 1714572300.123
+    Unix,
+    /// Seconds since following started, such as 12.345s
+    Relative,
+}
+
+/// Stamps each record read while following with when it was read,
+/// and with --delta how long after the record before it
+pub struct ArrivalStamps {
+    format: Option<TimestampFormat>,
+    delta: bool,
+    // Records are only stamped once following starts
+    started: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl ArrivalStamps {
+    pub fn new(format: Option<TimestampFormat>, delta: bool) -> ArrivalStamps {
+        ArrivalStamps {
+            format,
+            delta,
+            started: None,
+            last: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        let now = Instant::now();
+        self.started = Some(now);
+        self.last = Some(now);
+    }
+
+    /// The stamp for a record written now, with a trailing space
+    pub fn stamp(&mut self) -> Option<String> {
+        let started = self.started?;
+        let now = Instant::now();
+        let mut stamp = String::new();
+
+        match self.format {
+            Some(TimestampFormat::Rfc3339) => {
+                stamp.push_str(&Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true));
+                stamp.push(' ');
+            }
+            Some(TimestampFormat::Unix) => {
+                let time = Utc::now();
+                stamp.push_str(&format!(
+                    "{}.{:03} ",
+                    time.timestamp(),
+                    time.timestamp_subsec_millis()
+                ));
+            }
+            Some(TimestampFormat::Relative) => {
+                stamp.push_str(&format!("{:.3}s ", (now - started).as_secs_f64()));
+            }
+            None => {}
+        }
+
+        // The first record is measured from when following started
+        if self.delta {
+            let since = now - self.last.unwrap_or(started);
+            stamp.push_str(&format!("+{:.3}s ", since.as_secs_f64()));
+        }
+        self.last = Some(now);
+
+        Some(stamp)
+    }
+}
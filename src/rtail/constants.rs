@@ -1 +1,12 @@
+use std::time::Duration;
+
 pub const CHUNK_SIZE: u64 = 1024 * 64; // 64KB
+
+// How long follow mode waits for more continuation lines before emitting a record
+pub const RECORD_FLUSH_DELAY: Duration = Duration::from_millis(500);
+
+// How long --merge holds a followed record back for records from other files to catch up
+pub const MERGE_WINDOW: Duration = Duration::from_millis(500);
+
+// How often --follow-name looks for a file that has been moved or deleted
+pub const REOPEN_RETRY_DELAY: Duration = Duration::from_secs(1);
@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+use crate::rtail::event::RecordPos;
+
+/// What to do with a line once context has been taken into account
+pub enum ContextAction {
+    Skip,
+    Print {
+        separator: bool,
+        before: Vec<(Vec<u8>, bool, RecordPos)>,
+    },
+}
+
+/// Tracks the lines around matches, like grep's -A, -B and -C
+pub struct ContextWindow {
+    before: usize,
+    after: usize,
+    // Recent unprinted lines with their line numbers, whether they were terminated
+    // and where they came from
+    ring: VecDeque<(u64, Vec<u8>, bool, RecordPos)>,
+    after_left: usize,
+    line_no: u64,
+    last_printed: Option<u64>,
+}
+
+impl ContextWindow {
+    pub fn new(before: usize, after: usize) -> ContextWindow {
+        ContextWindow {
+            before,
+            after,
+            ring: VecDeque::with_capacity(before),
+            after_left: 0,
+            line_no: 0,
+            last_printed: None,
+        }
+    }
+
+    pub fn before(&self) -> usize {
+        self.before
+    }
+
+    pub fn push(
+        &mut self,
+        line: &[u8],
+        terminated: bool,
+        pos: RecordPos,
+        is_match: bool,
+    ) -> ContextAction {
+        self.line_no += 1;
+
+        if !is_match {
+            if self.after_left > 0 {
+                self.after_left -= 1;
+                return self.print(Vec::new(), self.line_no);
+            }
+
+            if self.before > 0 {
+                if self.ring.len() == self.before {
+                    self.ring.pop_front();
+                }
+                self.ring
+                    .push_back((self.line_no, line.to_vec(), terminated, pos));
+            }
+
+            return ContextAction::Skip;
+        }
+
+        self.after_left = self.after;
+        let first_line_no = self.ring.front().map_or(self.line_no, |(n, ..)| *n);
+        let before: Vec<(Vec<u8>, bool, RecordPos)> = self
+            .ring
+            .drain(..)
+            .map(|(_, line, terminated, pos)| (line, terminated, pos))
+            .collect();
+
+        self.print(before, first_line_no)
+    }
+
+    /// Forget everything, for example when moving on to another file
+    pub fn reset(&mut self) {
+        self.ring.clear();
+        self.after_left = 0;
+        self.line_no = 0;
+        self.last_printed = None;
+    }
+
+    fn print(
+        &mut self,
+        before: Vec<(Vec<u8>, bool, RecordPos)>,
+        first_line_no: u64,
+    ) -> ContextAction {
+        let has_context = self.before > 0 || self.after > 0;
+        let separator = has_context
+            && self
+                .last_printed
+                .is_some_and(|last| first_line_no > last + 1);
+        self.last_printed = Some(self.line_no);
+
+        ContextAction::Print { separator, before }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines printed for `lines`, where those starting with M match, with -- separators
+    fn printed(window: &mut ContextWindow, lines: &[&str]) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+
+        for line in lines {
+            let is_match = line.starts_with('M');
+            let ContextAction::Print { separator, before } =
+                window.push(line.as_bytes(), true, RecordPos::default(), is_match)
+            else {
+                continue;
+            };
+            if separator {
+                out.push("--".to_string());
+            }
+            out.extend(
+                before
+                    .into_iter()
+                    .map(|(line, ..)| String::from_utf8(line).unwrap()),
+            );
+            out.push(line.to_string());
+        }
+
+        out
+    }
+
+    #[test]
+    fn before_context_keeps_the_last_lines() {
+        let mut window = ContextWindow::new(2, 0);
+
+        assert_eq!(
+            printed(&mut window, &["a", "b", "c", "M1"]),
+            ["b", "c", "M1"]
+        );
+        // Lines already printed are not repeated
+        assert_eq!(printed(&mut window, &["M2", "d", "M3"]), ["M2", "d", "M3"]);
+    }
+
+    #[test]
+    fn after_context_counts_down() {
+        let mut window = ContextWindow::new(0, 2);
+
+        assert_eq!(
+            printed(&mut window, &["M1", "a", "b", "c", "M2", "M3", "d"]),
+            ["M1", "a", "b", "--", "M2", "M3", "d"]
+        );
+    }
+
+    #[test]
+    fn separator_only_between_groups_that_do_not_touch() {
+        let mut window = ContextWindow::new(1, 1);
+
+        assert_eq!(
+            printed(&mut window, &["M1", "a", "b", "M2", "c", "d", "e", "M3"]),
+            ["M1", "a", "b", "M2", "c", "--", "e", "M3"]
+        );
+    }
+
+    #[test]
+    fn no_separator_without_context() {
+        let mut window = ContextWindow::new(0, 0);
+
+        assert_eq!(printed(&mut window, &["M1", "a", "M2"]), ["M1", "M2"]);
+    }
+
+    #[test]
+    fn reset_forgets_lines_and_groups() {
+        let mut window = ContextWindow::new(1, 1);
+        printed(&mut window, &["M1", "a", "b"]);
+        window.reset();
+
+        assert_eq!(printed(&mut window, &["M2"]), ["M2"]);
+    }
+}
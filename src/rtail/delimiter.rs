@@ -0,0 +1,185 @@
+use memchr::memmem::{Finder, FinderRev};
+
+/// Byte sequence that separates records, such as `\n`, `\r\n` or `\x1e`
+#[derive(Clone, Debug)]
+pub struct Delimiter {
+    bytes: Vec<u8>,
+    finder: Finder<'static>,
+    finder_rev: FinderRev<'static>,
+    // Code unit size of the input encoding, a match must start on a multiple of it
+    unit: usize,
+}
+
+impl Delimiter {
+    pub fn new(bytes: &[u8]) -> Delimiter {
+        Delimiter::with_unit(bytes, 1)
+    }
+
+    /// A delimiter that only matches at file offsets that are a multiple of `unit`
+    pub fn with_unit(bytes: &[u8], unit: usize) -> Delimiter {
+        Delimiter {
+            bytes: bytes.to_vec(),
+            finder: Finder::new(bytes).into_owned(),
+            finder_rev: FinderRev::new(bytes).into_owned(),
+            unit,
+        }
+    }
+
+    /// Parse a delimiter given on the command line, supporting
+    /// `\n`, `\r`, `\t`, `\0`, `\\` and `\xHH` escapes
+    pub fn parse(input: &str) -> Result<Delimiter, String> {
+        let bytes = unescape(input)?;
+        if bytes.is_empty() {
+            return Err("delimiter cannot be empty".to_string());
+        }
+
+        Ok(Delimiter::new(&bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        self.finder.find(haystack)
+    }
+
+    /// Like `find`, for a haystack read from file offset `base`
+    pub fn find_at(&self, haystack: &[u8], base: u64) -> Option<usize> {
+        self.find_iter_at(haystack, base).next()
+    }
+
+    /// Like `find_iter`, for a haystack read from file offset `base`
+    pub fn find_iter_at<'h>(
+        &'h self,
+        haystack: &'h [u8],
+        base: u64,
+    ) -> impl Iterator<Item = usize> + 'h {
+        self.finder
+            .find_iter(haystack)
+            .filter(move |&idx| self.is_aligned(base + idx as u64))
+    }
+
+    /// Like `rfind`, for a haystack read from file offset `base`
+    pub fn rfind_at(&self, haystack: &[u8], base: u64) -> Option<usize> {
+        let mut end = haystack.len();
+
+        while let Some(idx) = self.finder_rev.rfind(&haystack[..end]) {
+            if self.is_aligned(base + idx as u64) {
+                return Some(idx);
+            }
+            end = idx + self.bytes.len() - 1;
+        }
+
+        None
+    }
+
+    fn is_aligned(&self, offset: u64) -> bool {
+        offset.is_multiple_of(self.unit as u64)
+    }
+
+    pub fn is_suffix_of(&self, haystack: &[u8]) -> bool {
+        haystack.ends_with(&self.bytes)
+    }
+
+    /// Length of the longest end of `haystack` that could be the start of a delimiter
+    /// split across two reads
+    pub fn partial_suffix_len(&self, haystack: &[u8]) -> usize {
+        let max = (self.bytes.len() - 1).min(haystack.len());
+
+        (1..=max)
+            .rev()
+            .find(|&len| haystack.ends_with(&self.bytes[..len]))
+            .unwrap_or(0)
+    }
+}
+
+fn unescape(input: &str) -> Result<Vec<u8>, String> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(input.len());
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(b'\0'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape '\\x{}' in '{}'", hex, input))?;
+                bytes.push(byte);
+            }
+            Some(other) => return Err(format!("unknown escape '\\{}' in '{}'", other, input)),
+            None => return Err(format!("trailing backslash in '{}'", input)),
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_escapes() {
+        assert_eq!(Delimiter::parse(r"\r\n").unwrap().as_bytes(), b"\r\n");
+        assert_eq!(Delimiter::parse(r"\x1e").unwrap().as_bytes(), b"\x1e");
+        assert_eq!(Delimiter::parse(r"\0\t\\").unwrap().as_bytes(), b"\0\t\\");
+        assert_eq!(Delimiter::parse("---").unwrap().as_bytes(), b"---");
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        assert!(Delimiter::parse("").is_err());
+        assert!(Delimiter::parse(r"\q").is_err());
+        assert!(Delimiter::parse(r"\xZZ").is_err());
+        assert!(Delimiter::parse("a\\").is_err());
+    }
+
+    #[test]
+    fn partial_suffix_of_split_delimiter() {
+        let delimiter = Delimiter::new(b"\r\n");
+        assert_eq!(delimiter.partial_suffix_len(b"abc\r"), 1);
+        assert_eq!(delimiter.partial_suffix_len(b"abc\r\n"), 0);
+        assert_eq!(delimiter.partial_suffix_len(b"abc"), 0);
+
+        let delimiter = Delimiter::new(b"---");
+        assert_eq!(delimiter.partial_suffix_len(b"a--"), 2);
+        assert_eq!(delimiter.partial_suffix_len(b"a-"), 1);
+        assert_eq!(delimiter.partial_suffix_len(b"-"), 1);
+    }
+
+    #[test]
+    fn matches_only_on_unit_boundaries() {
+        // UTF-16LE "a\n\n"
+        let delimiter = Delimiter::with_unit(b"\n\0", 2);
+        let haystack = b"a\0\n\0\n\0";
+
+        let found: Vec<usize> = delimiter.find_iter_at(haystack, 0).collect();
+        assert_eq!(found, vec![2, 4]);
+        let found: Vec<usize> = delimiter.find_iter_at(&haystack[1..], 1).collect();
+        assert_eq!(found, vec![1, 3]);
+        assert_eq!(delimiter.find_at(b"\n\0", 1), None);
+    }
+
+    #[test]
+    fn rfind_skips_unaligned_matches() {
+        let delimiter = Delimiter::with_unit(b"\n\0", 2);
+        assert_eq!(delimiter.rfind_at(b"\n\0\n\0x", 0), Some(2));
+        assert_eq!(delimiter.rfind_at(b"x\n\0", 0), None);
+        assert_eq!(delimiter.rfind_at(b"x\n\0", 1), Some(1));
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+/// Parse durations such as `500ms`, `60s`, `1.5m`, `1h30m` or a plain number of seconds
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+
+    // A bare number is taken as seconds
+    if let Ok(secs) = input.parse::<f64>() {
+        return seconds_to_duration(secs, input);
+    }
+
+    let mut total: f64 = 0.0;
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let unit_len = rest[number_len..]
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len() - number_len);
+
+        let number: f64 = rest[..number_len]
+            .parse()
+            .map_err(|_| format!("invalid duration '{}'", input))?;
+        let scale: f64 = match &rest[number_len..number_len + unit_len] {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 60.0 * 60.0,
+            "d" => 24.0 * 60.0 * 60.0,
+            unit => return Err(format!("unknown unit '{}' in duration '{}'", unit, input)),
+        };
+
+        total += number * scale;
+        rest = &rest[number_len + unit_len..];
+    }
+
+    seconds_to_duration(total, input)
+}
+
+fn seconds_to_duration(secs: f64, input: &str) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid duration '{}'", input))
+}
+
+/// Write a duration the way it is parsed, such as `4m12s` or `1h30m`, to the second
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        return format!("{}ms", duration.as_millis());
+    }
+
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    units
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, unit)| format!("{}{}", count, unit))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172800)));
+    }
+
+    #[test]
+    fn parse_bare_seconds() {
+        assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_duration(" 0.25 "), Ok(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("-5").is_err());
+    }
+
+    #[test]
+    fn format_round_trips() {
+        assert_eq!(format_duration(Duration::from_secs(252)), "4m12s");
+        assert_eq!(format_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(format_duration(Duration::from_secs(90061)), "1d1h1m1s");
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+
+        for input in ["4m12s", "1h30m", "1d1h1m1s", "250ms"] {
+            assert_eq!(format_duration(parse_duration(input).unwrap()), input);
+        }
+    }
+}
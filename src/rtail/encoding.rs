@@ -0,0 +1,141 @@
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use std::{borrow::Cow, fs::File, os::unix::fs::FileExt};
+
+use crate::rtail::delimiter::Delimiter;
+
+/// Parse an encoding name given on the command line, such as `utf-16le` or `windows-1252`
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding '{}'", label))
+}
+
+/// The encoding of `file`: `forced` if given, otherwise from its byte order mark,
+/// falling back to UTF-8
+pub fn detect_encoding(
+    file: &File,
+    forced: Option<&'static Encoding>,
+) -> std::io::Result<&'static Encoding> {
+    if let Some(encoding) = forced {
+        return Ok(encoding);
+    }
+
+    let mut bom = [0; 3];
+    let bytes_read = file.read_at(&mut bom, 0)?;
+
+    Ok(Encoding::for_bom(&bom[..bytes_read]).map_or(UTF_8, |(encoding, _)| encoding))
+}
+
+/// Length of the byte order mark at the start of `file`, 0 if it has none
+pub fn bom_len(file: &File) -> std::io::Result<u64> {
+    let mut bom = [0; 3];
+    let bytes_read = file.read_at(&mut bom, 0)?;
+
+    Ok(Encoding::for_bom(&bom[..bytes_read]).map_or(0, |(_, len)| len as u64))
+}
+
+/// Size in bytes of the code units of `encoding`
+pub fn code_unit_size(encoding: &'static Encoding) -> usize {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        2
+    } else {
+        1
+    }
+}
+
+/// A UTF-8 delimiter as it appears in a file of the given encoding
+pub fn encode_delimiter(delimiter: &Delimiter, encoding: &'static Encoding) -> Delimiter {
+    if encoding == UTF_8 {
+        return delimiter.clone();
+    }
+
+    let text = String::from_utf8_lossy(delimiter.as_bytes());
+    let bytes: Vec<u8> = if encoding == UTF_16LE {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    } else if encoding == UTF_16BE {
+        text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    } else {
+        encoding.encode(&text).0.into_owned()
+    };
+
+    Delimiter::with_unit(&bytes, code_unit_size(encoding))
+}
+
+/// Transcode a whole line to UTF-8; UTF-8 input is passed through untouched
+pub fn decode_line<'a>(encoding: &'static Encoding, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+    if encoding == UTF_8 {
+        return Cow::Borrowed(bytes);
+    }
+
+    match encoding.decode_without_bom_handling(bytes).0 {
+        Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+        Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::WINDOWS_1252;
+
+    use super::*;
+    use crate::rtail::test_util::temp_file;
+
+    #[test]
+    fn detects_byte_order_marks() {
+        let detected =
+            |contents: &[u8]| detect_encoding(&temp_file("bom", contents), None).unwrap();
+
+        assert_eq!(detected(b"\xEF\xBB\xBFone\n"), UTF_8);
+        assert_eq!(detected(b"\xFF\xFEo\0"), UTF_16LE);
+        assert_eq!(detected(b"\xFE\xFF\0o"), UTF_16BE);
+        assert_eq!(detected(b"one\n"), UTF_8);
+        assert_eq!(detected(b""), UTF_8);
+    }
+
+    #[test]
+    fn byte_order_mark_lengths() {
+        let bom_len_of = |contents: &[u8]| bom_len(&temp_file("bom-len", contents)).unwrap();
+
+        assert_eq!(bom_len_of(b"\xEF\xBB\xBFone\n"), 3);
+        assert_eq!(bom_len_of(b"\xFF\xFEo\0"), 2);
+        assert_eq!(bom_len_of(b"one\n"), 0);
+        assert_eq!(bom_len_of(b""), 0);
+    }
+
+    #[test]
+    fn forced_encoding_wins_over_bom() {
+        let file = temp_file("forced", b"\xFF\xFEo\0");
+        assert_eq!(
+            detect_encoding(&file, Some(WINDOWS_1252)).unwrap(),
+            WINDOWS_1252
+        );
+    }
+
+    #[test]
+    fn parse_encoding_labels() {
+        assert_eq!(parse_encoding("utf-16le"), Ok(UTF_16LE));
+        assert_eq!(parse_encoding("latin1"), Ok(WINDOWS_1252));
+        assert!(parse_encoding("klingon").is_err());
+    }
+
+    #[test]
+    fn delimiters_in_the_input_encoding() {
+        let newline = Delimiter::new(b"\r\n");
+
+        assert_eq!(encode_delimiter(&newline, UTF_16LE).as_bytes(), b"\r\0\n\0");
+        assert_eq!(encode_delimiter(&newline, UTF_16BE).as_bytes(), b"\0\r\0\n");
+        assert_eq!(encode_delimiter(&newline, WINDOWS_1252).as_bytes(), b"\r\n");
+        assert_eq!(
+            encode_delimiter(&Delimiter::new("§".as_bytes()), WINDOWS_1252).as_bytes(),
+            b"\xA7"
+        );
+    }
+
+    #[test]
+    fn decodes_lines_to_utf8() {
+        assert_eq!(&*decode_line(UTF_16LE, b"h\0\xE9\0"), "hé".as_bytes());
+        assert_eq!(&*decode_line(WINDOWS_1252, b"caf\xE9"), "café".as_bytes());
+        assert!(matches!(
+            decode_line(UTF_8, b"\xFF"),
+            Cow::Borrowed(b"\xFF")
+        ));
+    }
+}
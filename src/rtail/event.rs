@@ -0,0 +1,172 @@
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+/// How records are written out
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// As they are
+    Text,
+    /// One JSON object per record or file event
+    Json,
+}
+
+/// Where a record starts in its file, when that is known
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecordPos {
+    pub offset: Option<u64>,
+    pub line_number: Option<u64>,
+}
+
+/// Something that happened to a followed file, other than new data
+#[derive(Clone, Copy, Debug)]
+pub enum FileEvent {
+    Rotated,
+    Truncated,
+    Deleted,
+}
+
+impl FileEvent {
+    fn name(self) -> &'static str {
+        match self {
+            FileEvent::Rotated => "rotated",
+            FileEvent::Truncated => "truncated",
+            FileEvent::Deleted => "deleted",
+        }
+    }
+}
+
+/// The file records are read from
+#[derive(Clone, Debug, Default)]
+pub struct Source {
+    pub name: String,
+    pub inode: u64,
+}
+
+/// A record as a JSON object, without a trailing newline
+pub fn record_event(source: &Source, pos: RecordPos, data: &[u8]) -> Vec<u8> {
+    let mut object = source_fields(source);
+    if let Some(offset) = pos.offset {
+        object.insert("offset".to_string(), Value::from(offset));
+    }
+    if let Some(line_number) = pos.line_number {
+        object.insert("line_number".to_string(), Value::from(line_number));
+    }
+    object.insert("received_at".to_string(), Value::from(received_at()));
+    object.insert(
+        "data".to_string(),
+        Value::from(String::from_utf8_lossy(data)),
+    );
+
+    Value::Object(object).to_string().into_bytes()
+}
+
+/// A file event as a JSON object, without a trailing newline
+pub fn file_event(source: &Source, event: FileEvent) -> Vec<u8> {
+    let mut object = Map::new();
+    object.insert("event".to_string(), Value::from(event.name()));
+    object.extend(source_fields(source));
+    object.insert("received_at".to_string(), Value::from(received_at()));
+
+    Value::Object(object).to_string().into_bytes()
+}
+
+fn source_fields(source: &Source) -> Map<String, Value> {
+    let mut object = Map::new();
+    object.insert("file".to_string(), Value::from(source.name.as_str()));
+    object.insert("inode".to_string(), Value::from(source.inode));
+    object
+}
+
+fn received_at() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtail::test_util::{output, printer};
+
+    fn parsed(event: &[u8]) -> Map<String, Value> {
+        match serde_json::from_slice(event).unwrap() {
+            Value::Object(object) => object,
+            _ => panic!("events are objects"),
+        }
+    }
+
+    fn source() -> Source {
+        Source {
+            name: "app.log".to_string(),
+            inode: 42,
+        }
+    }
+
+    #[test]
+    fn record_with_position() {
+        let pos = RecordPos {
+            offset: Some(10),
+            line_number: Some(3),
+        };
+        let event = parsed(&record_event(&source(), pos, b"hello \xFF"));
+
+        assert_eq!(
+            event.keys().collect::<Vec<&String>>(),
+            [
+                "file",
+                "inode",
+                "offset",
+                "line_number",
+                "received_at",
+                "data"
+            ]
+        );
+        assert_eq!(event["file"], "app.log");
+        assert_eq!(event["inode"], 42);
+        assert_eq!(event["offset"], 10);
+        assert_eq!(event["line_number"], 3);
+        assert_eq!(event["data"], "hello \u{FFFD}");
+        assert!(
+            event["received_at"]
+                .as_str()
+                .is_some_and(|time| time.ends_with('Z'))
+        );
+    }
+
+    #[test]
+    fn record_without_position() {
+        let event = parsed(&record_event(&source(), RecordPos::default(), b"x"));
+
+        assert!(!event.contains_key("offset"));
+        assert!(!event.contains_key("line_number"));
+    }
+
+    #[test]
+    fn file_events() {
+        for (kind, name) in [
+            (FileEvent::Rotated, "rotated"),
+            (FileEvent::Truncated, "truncated"),
+            (FileEvent::Deleted, "deleted"),
+        ] {
+            let event = parsed(&file_event(&source(), kind));
+            assert_eq!(event["event"], name);
+            assert_eq!(event["file"], "app.log");
+            assert_eq!(event["inode"], 42);
+        }
+    }
+
+    #[test]
+    fn printer_writes_one_event_per_record() {
+        let mut printer = printer(&["--output", "json"]);
+        printer.seek(4);
+
+        let events: Vec<Map<String, Value>> = output(&mut printer, b"one\ntwo")
+            .lines()
+            .map(|line| parsed(line.as_bytes()))
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["data"], "one");
+        assert_eq!(events[0]["offset"], 4);
+        assert_eq!(events[1]["data"], "two");
+        assert_eq!(events[1]["offset"], 8);
+    }
+}
@@ -0,0 +1,398 @@
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+
+use crate::rtail::json::{lookup, value_text};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Path(String),
+    Literal(Value),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Path(String),
+    Literal(Value),
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Operand, &'static str, Operand),
+    Matches(Operand, Regex, bool),
+    // A field on its own is true when it is present and not null or false
+    Truthy(Operand),
+}
+
+/// Filter expression such as `level == "error" && status >= 500 && path ~ "^/api"`
+#[derive(Clone, Debug)]
+pub struct Expr {
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Expr, String> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.or()?;
+
+        if parser.pos < parser.tokens.len() {
+            return Err(format!(
+                "unexpected {:?} in '{}'",
+                parser.tokens[parser.pos], input
+            ));
+        }
+
+        Ok(Expr { root })
+    }
+
+    /// Evaluate against the fields of a record
+    pub fn matches(&self, fields: &Map<String, Value>) -> bool {
+        eval(&self.root, fields)
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        // Longest operators first
+        let rest = &input[start..];
+        if let Some(op) = ["==", "!=", "<=", ">=", "!~", "&&", "||", "<", ">", "~", "!"]
+            .into_iter()
+            .find(|op| rest.starts_with(op))
+        {
+            tokens.push(Token::Op(op));
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            continue;
+        }
+
+        match c {
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                let mut closed = false;
+                while let Some((_, c2)) = chars.next() {
+                    match c2 {
+                        '\\' => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, escaped)) => text.push(escaped),
+                            None => break,
+                        },
+                        _ if c2 == c => {
+                            closed = true;
+                            break;
+                        }
+                        _ => text.push(c2),
+                    }
+                }
+                if !closed {
+                    return Err(format!("unclosed string in '{}'", input));
+                }
+                tokens.push(Token::Literal(Value::String(text)));
+            }
+            _ if c.is_ascii_digit() || c == '-' => {
+                let end = rest
+                    .char_indices()
+                    .skip(1)
+                    .find(|&(_, c2)| !(c2.is_ascii_alphanumeric() || c2 == '.'))
+                    .map_or(rest.len(), |(idx, _)| idx);
+                let number: f64 = rest[..end]
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}' in '{}'", &rest[..end], input))?;
+                tokens.push(Token::Literal(Value::from(number)));
+                for _ in rest[..end].chars() {
+                    chars.next();
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' || c == '@' => {
+                let end = rest
+                    .char_indices()
+                    .find(|&(_, c2)| !(c2.is_alphanumeric() || matches!(c2, '_' | '.' | '-' | '@')))
+                    .map_or(rest.len(), |(idx, _)| idx);
+                let word = &rest[..end];
+                tokens.push(match word {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    _ => Token::Path(word.to_string()),
+                });
+                for _ in word.chars() {
+                    chars.next();
+                }
+            }
+            _ => return Err(format!("unexpected '{}' in '{}'", c, input)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.peek() == Some(&Token::Op("||")) {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while self.peek() == Some(&Token::Op("&&")) {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                Ok(Node::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let node = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(node),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let left = self.operand()?;
+
+        let op = match self.peek() {
+            Some(Token::Op(op)) if !matches!(*op, "&&" | "||" | "!") => *op,
+            _ => return Ok(Node::Truthy(left)),
+        };
+        self.pos += 1;
+
+        if op == "~" || op == "!~" {
+            let pattern = match self.next() {
+                Some(Token::Literal(Value::String(pattern))) => pattern,
+                _ => return Err(format!("'{}' needs a quoted regex on its right", op)),
+            };
+            let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+            return Ok(Node::Matches(left, regex, op == "~"));
+        }
+
+        Ok(Node::Compare(left, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Path(path)) => Ok(Operand::Path(path)),
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(token) => Err(format!("expected a field or value, found {:?}", token)),
+            None => Err("expression ends early".to_string()),
+        }
+    }
+}
+
+fn resolve<'a>(operand: &'a Operand, fields: &'a Map<String, Value>) -> Option<&'a Value> {
+    match operand {
+        Operand::Path(path) => lookup(fields, path),
+        Operand::Literal(value) => Some(value),
+    }
+}
+
+fn eval(node: &Node, fields: &Map<String, Value>) -> bool {
+    match node {
+        Node::And(left, right) => eval(left, fields) && eval(right, fields),
+        Node::Or(left, right) => eval(left, fields) || eval(right, fields),
+        Node::Not(inner) => !eval(inner, fields),
+        Node::Truthy(operand) => !matches!(
+            resolve(operand, fields),
+            None | Some(Value::Null | Value::Bool(false))
+        ),
+        Node::Matches(operand, regex, want) => resolve(operand, fields)
+            .is_some_and(|value| regex.is_match(&value_text(value)) == *want),
+        Node::Compare(left, op, right) => {
+            let ordering = match (resolve(left, fields), resolve(right, fields)) {
+                (Some(left), Some(right)) => compare(left, right),
+                // A missing field only equals null
+                (None, Some(Value::Null)) | (Some(Value::Null), None) => Some(Ordering::Equal),
+                _ => None,
+            };
+
+            match (*op, ordering) {
+                ("==", Some(ordering)) => ordering == Ordering::Equal,
+                ("!=", Some(ordering)) => ordering != Ordering::Equal,
+                ("!=", None) => true,
+                ("<", Some(ordering)) => ordering == Ordering::Less,
+                ("<=", Some(ordering)) => ordering != Ordering::Greater,
+                (">", Some(ordering)) => ordering == Ordering::Greater,
+                (">=", Some(ordering)) => ordering != Ordering::Less,
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Numbers compare as numbers, also when the other side arrived as text, anything else as text
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    if left.is_number() || right.is_number() {
+        return to_number(left)?.partial_cmp(&to_number(right)?);
+    }
+
+    Some(value_text(left).cmp(&value_text(right)))
+}
+
+fn to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn matches(expr: &str, record: Value) -> bool {
+        match record {
+            Value::Object(fields) => Expr::parse(expr).unwrap().matches(&fields),
+            _ => panic!("records are objects"),
+        }
+    }
+
+    #[test]
+    fn tokenizes_operators_and_literals() {
+        assert_eq!(
+            tokenize(r#"a.b>=-1.5&&!(c!~'x\'y')"#).unwrap(),
+            vec![
+                Token::Path("a.b".to_string()),
+                Token::Op(">="),
+                Token::Literal(json!(-1.5)),
+                Token::Op("&&"),
+                Token::Op("!"),
+                Token::Open,
+                Token::Path("c".to_string()),
+                Token::Op("!~"),
+                Token::Literal(json!("x'y")),
+                Token::Close,
+            ]
+        );
+        assert_eq!(
+            tokenize("true false null @timestamp").unwrap(),
+            vec![
+                Token::Literal(json!(true)),
+                Token::Literal(json!(false)),
+                Token::Literal(Value::Null),
+                Token::Path("@timestamp".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_numbers_and_text() {
+        let record = json!({"level": "error", "status": 503, "took": "12.5"});
+
+        assert!(matches(r#"level == "error""#, record.clone()));
+        assert!(matches("status >= 500", record.clone()));
+        assert!(!matches("status < 500", record.clone()));
+        // Numbers in strings compare as numbers
+        assert!(matches("took > 9", record.clone()));
+        assert!(matches(r#"level != "info""#, record.clone()));
+        assert!(matches(r#"status == "503""#, record));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let record = json!({"a": 1, "b": 2});
+
+        assert!(matches("a == 1 || a == 2 && b == 3", record.clone()));
+        assert!(!matches("(a == 1 || a == 2) && b == 3", record.clone()));
+        assert!(matches("!(b == 3) && a == 1", record));
+    }
+
+    #[test]
+    fn matches_regexes() {
+        let record = json!({"path": "/api/users", "code": 404});
+
+        assert!(matches(r#"path ~ "^/api""#, record.clone()));
+        assert!(!matches(r#"path !~ "^/api""#, record.clone()));
+        assert!(matches(r#"code ~ "^4""#, record.clone()));
+        assert!(!matches(r#"missing ~ ".""#, record));
+    }
+
+    #[test]
+    fn missing_fields_and_truthiness() {
+        let record = json!({"user": {"id": 7, "admin": false}, "tags": ["a", "b"], "gone": null});
+
+        assert!(matches("user.id == 7", record.clone()));
+        assert!(matches(r#"tags.1 == "b""#, record.clone()));
+        assert!(matches("user.id", record.clone()));
+        assert!(!matches("user.admin", record.clone()));
+        assert!(!matches("gone", record.clone()));
+        assert!(matches("missing == null && gone == null", record.clone()));
+        assert!(matches("missing != 1", record.clone()));
+        assert!(!matches("missing < 1", record));
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        for input in [
+            "",
+            "a ==",
+            "(a == 1",
+            "a == 1)",
+            r#"a == "open"#,
+            "a ~ b",
+            r#"a ~ "(""#,
+            "a == 1 b",
+            "a # 1",
+            "1x == 1",
+        ] {
+            assert!(Expr::parse(input).is_err(), "'{}' should not parse", input);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::borrow::Cow;
+
+use crate::rtail::delimiter::Delimiter;
+
+/// One entry of a --fields list
+#[derive(Clone, Debug)]
+enum FieldSelector {
+    // Zero-based, inclusive, with an open end if None
+    Range(usize, Option<usize>),
+    Name(String),
+}
+
+/// Fields to keep, such as `1,3,5-7` or `ts,status`, in output order
+#[derive(Clone, Debug)]
+pub struct FieldList {
+    selectors: Vec<FieldSelector>,
+    // The entries as given, which are all key names for JSON records
+    keys: Vec<String>,
+}
+
+impl FieldList {
+    /// Parse a comma-separated list of field numbers, ranges and header names
+    pub fn parse(input: &str) -> Result<FieldList, String> {
+        let selectors = input
+            .split(',')
+            .map(parse_selector)
+            .collect::<Result<Vec<FieldSelector>, String>>()?;
+        let keys = input.split(',').map(|key| key.trim().to_string()).collect();
+
+        Ok(FieldList { selectors, keys })
+    }
+
+    pub fn has_names(&self) -> bool {
+        self.selectors
+            .iter()
+            .any(|selector| matches!(selector, FieldSelector::Name(_)))
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Names of the fields to keep, for inputs where fields are only known by name
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.selectors.iter().filter_map(|selector| match selector {
+            FieldSelector::Name(name) => Some(name.as_str()),
+            FieldSelector::Range(..) => None,
+        })
+    }
+}
+
+fn parse_selector(input: &str) -> Result<FieldSelector, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty field in list".to_string());
+    }
+
+    let position = |number: &str| -> Result<usize, String> {
+        match number.parse::<usize>() {
+            Ok(0) => Err("fields are numbered from 1".to_string()),
+            Ok(n) => Ok(n - 1),
+            Err(e) => Err(format!("invalid field number '{}': {}", number, e)),
+        }
+    };
+    let is_number = |text: &str| !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit());
+
+    match input.split_once('-') {
+        // Anything else with a dash, such as `user-agent`, is a name
+        Some((start, end))
+            if (is_number(start) || start.is_empty())
+                && (is_number(end) || end.is_empty())
+                && !(start.is_empty() && end.is_empty()) =>
+        {
+            let start = if start.is_empty() {
+                0
+            } else {
+                position(start)?
+            };
+            let end = if end.is_empty() {
+                None
+            } else {
+                Some(position(end)?)
+            };
+            if end.is_some_and(|end| end < start) {
+                return Err(format!("decreasing field range '{}'", input));
+            }
+            Ok(FieldSelector::Range(start, end))
+        }
+        _ if is_number(input) => {
+            let n = position(input)?;
+            Ok(FieldSelector::Range(n, Some(n)))
+        }
+        _ => Ok(FieldSelector::Name(input.to_string())),
+    }
+}
+
+/// Parse the single-byte separator between fields, such as `,` or `\t`
+pub fn parse_separator(input: &str) -> Result<u8, String> {
+    match Delimiter::parse(input)?.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(format!("field separator '{}' must be a single byte", input)),
+    }
+}
+
+/// Splits lines into fields, keeps the selected ones and optionally pads them into columns
+pub struct Columns {
+    fields: Option<FieldList>,
+    // Separator for quoted CSV parsing, or None to split on runs of whitespace
+    separator: Option<u8>,
+    align: bool,
+    // Field numbers of the names in `fields`, once the header line has been seen
+    name_positions: Vec<Option<usize>>,
+    // Widest value seen so far in each output column
+    widths: Vec<usize>,
+}
+
+impl Columns {
+    pub fn new(fields: Option<FieldList>, separator: Option<u8>, align: bool) -> Columns {
+        Columns {
+            fields,
+            separator,
+            align,
+            name_positions: Vec::new(),
+            widths: Vec::new(),
+        }
+    }
+
+    /// Look up the field names in the header line
+    pub fn set_header(&mut self, line: &[u8]) {
+        let header = self.split(line);
+
+        if let Some(fields) = &self.fields {
+            self.name_positions = fields
+                .names()
+                .map(|name| {
+                    header
+                        .iter()
+                        .position(|field| field.as_ref() == name.as_bytes())
+                })
+                .collect();
+        }
+    }
+
+    /// The line with only the selected fields, joined the way they were read,
+    /// or padded to the column widths seen so far with --align
+    pub fn render(&mut self, line: &[u8]) -> Vec<u8> {
+        let fields = self.split(line);
+        let selected = self.select(&fields);
+
+        if self.align {
+            return self.pad(&selected);
+        }
+
+        match self.separator {
+            Some(separator) => {
+                let mut writer = csv::WriterBuilder::new()
+                    .delimiter(separator)
+                    .from_writer(Vec::new());
+                // Writing to a Vec can't fail
+                let _ = writer.write_record(&selected);
+                let mut joined = writer.into_inner().unwrap_or_default();
+                joined.pop(); // The record terminator
+                joined
+            }
+            None => selected.join(&b' '),
+        }
+    }
+
+    fn split<'a>(&self, line: &'a [u8]) -> Vec<Cow<'a, [u8]>> {
+        match self.separator {
+            Some(separator) => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .delimiter(separator)
+                    .from_reader(line);
+                let mut record = csv::ByteRecord::new();
+
+                match reader.read_byte_record(&mut record) {
+                    Ok(true) => record
+                        .iter()
+                        .map(|field| Cow::Owned(field.to_vec()))
+                        .collect(),
+                    // Not valid CSV, so keep the whole line as one field
+                    _ => vec![Cow::Borrowed(line)],
+                }
+            }
+            None => line
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|field| !field.is_empty())
+                .map(Cow::Borrowed)
+                .collect(),
+        }
+    }
+
+    fn select<'a>(&self, fields: &'a [Cow<'a, [u8]>]) -> Vec<&'a [u8]> {
+        let Some(list) = &self.fields else {
+            return fields.iter().map(|field| field.as_ref()).collect();
+        };
+
+        let mut selected: Vec<&[u8]> = Vec::new();
+        let mut names = self.name_positions.iter();
+
+        for selector in &list.selectors {
+            match selector {
+                FieldSelector::Range(start, Some(end)) if start == end => {
+                    // A single field is always a column, even when a line is short of it
+                    selected.push(fields.get(*start).map_or(&[], |field| field.as_ref()));
+                }
+                FieldSelector::Range(start, end) => {
+                    let end = end.map_or(fields.len(), |end| (end + 1).min(fields.len()));
+                    selected.extend(
+                        fields
+                            .get(*start..end)
+                            .unwrap_or_default()
+                            .iter()
+                            .map(|field| field.as_ref()),
+                    );
+                }
+                FieldSelector::Name(_) => {
+                    let position = names.next().copied().flatten();
+                    selected.push(
+                        position
+                            .and_then(|idx| fields.get(idx))
+                            .map_or(&[], |field| field.as_ref()),
+                    );
+                }
+            }
+        }
+
+        selected
+    }
+
+    fn pad(&mut self, selected: &[&[u8]]) -> Vec<u8> {
+        let mut padded: Vec<u8> = Vec::new();
+
+        for (idx, field) in selected.iter().enumerate() {
+            let width = String::from_utf8_lossy(field).chars().count();
+            if idx == self.widths.len() {
+                self.widths.push(width);
+            }
+            self.widths[idx] = self.widths[idx].max(width);
+
+            padded.extend_from_slice(field);
+            if idx + 1 < selected.len() {
+                padded.resize(padded.len() + self.widths[idx] - width + 2, b' ');
+            }
+        }
+
+        padded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(fields: Option<&str>, separator: Option<u8>, align: bool) -> Columns {
+        Columns::new(
+            fields.map(|fields| FieldList::parse(fields).unwrap()),
+            separator,
+            align,
+        )
+    }
+
+    fn render(columns: &mut Columns, line: &str) -> String {
+        String::from_utf8(columns.render(line.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn parse_field_list() {
+        let list = FieldList::parse("1,3,5-7").unwrap();
+        assert!(matches!(
+            list.selectors[..],
+            [
+                FieldSelector::Range(0, Some(0)),
+                FieldSelector::Range(2, Some(2)),
+                FieldSelector::Range(4, Some(6)),
+            ]
+        ));
+        assert!(!list.has_names());
+
+        let list = FieldList::parse("ts, user-agent,-2,4-").unwrap();
+        assert_eq!(list.names().collect::<Vec<&str>>(), ["ts", "user-agent"]);
+        assert_eq!(list.keys(), ["ts", "user-agent", "-2", "4-"]);
+        assert!(matches!(
+            list.selectors[2..],
+            [
+                FieldSelector::Range(0, Some(1)),
+                FieldSelector::Range(3, None)
+            ]
+        ));
+    }
+
+    #[test]
+    fn parse_field_list_rejects_bad_input() {
+        assert!(FieldList::parse("0").is_err());
+        assert!(FieldList::parse("1,,2").is_err());
+        assert!(FieldList::parse("5-3").is_err());
+        assert!(FieldList::parse("-").is_ok_and(|list| list.has_names()));
+    }
+
+    #[test]
+    fn select_by_number_from_whitespace() {
+        let mut columns = columns(Some("3,1,5-"), None, false);
+
+        assert_eq!(render(&mut columns, "a  b\tc d e"), "c a e");
+        // A missing single field still takes its place
+        assert_eq!(render(&mut columns, "a b"), " a");
+    }
+
+    #[test]
+    fn select_by_header_name() {
+        let mut columns = columns(Some("status,ts,missing"), Some(b','), false);
+        columns.set_header(b"ts,path,status");
+
+        assert_eq!(render(&mut columns, "10:00,/,200"), "200,10:00,");
+    }
+
+    #[test]
+    fn quoted_csv_fields() {
+        let mut columns = columns(Some("2,1"), Some(b','), false);
+
+        assert_eq!(
+            render(&mut columns, r#""Smith, J","said ""hi""""#),
+            r#""said ""hi""","Smith, J""#
+        );
+        assert_eq!(render(&mut columns, "a;b,c"), "c,a;b");
+    }
+
+    #[test]
+    fn align_widens_columns() {
+        let mut columns = columns(None, None, true);
+
+        assert_eq!(render(&mut columns, "a bb c"), "a  bb  c");
+        assert_eq!(render(&mut columns, "aaaa b c"), "aaaa  b   c");
+        // Columns never shrink back
+        assert_eq!(render(&mut columns, "a b c"), "a     b   c");
+        assert_eq!(render(&mut columns, "é b"), "é     b");
+    }
+
+    #[test]
+    fn parse_single_byte_separator() {
+        assert_eq!(parse_separator(","), Ok(b','));
+        assert_eq!(parse_separator("\\t"), Ok(b'\t'));
+        assert!(parse_separator("::").is_err());
+    }
+}
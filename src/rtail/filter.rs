@@ -0,0 +1,177 @@
+use regex::bytes::{RegexSet, RegexSetBuilder};
+use serde_json::{Map, Value};
+
+use crate::rtail::{
+    expr::Expr,
+    parse::{LineFormat, parse_fields},
+};
+
+/// Include and exclude patterns, and a --where expression, applied to every line
+pub struct LineFilter {
+    include: Option<RegexSet>,
+    exclude: Option<RegexSet>,
+    where_expr: Option<Expr>,
+    // Format from --parse; lines not in it only pass with --show-unparsed
+    format: Option<LineFormat>,
+    show_unparsed: bool,
+}
+
+impl LineFilter {
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        ignore_case: bool,
+        where_expr: Option<Expr>,
+        format: Option<LineFormat>,
+        show_unparsed: bool,
+    ) -> Result<LineFilter, regex::Error> {
+        let build = |patterns: &[String]| -> Result<Option<RegexSet>, regex::Error> {
+            if patterns.is_empty() {
+                return Ok(None);
+            }
+
+            RegexSetBuilder::new(patterns)
+                .case_insensitive(ignore_case)
+                .build()
+                .map(Some)
+        };
+
+        Ok(LineFilter {
+            include: build(include)?,
+            exclude: build(exclude)?,
+            where_expr,
+            format,
+            show_unparsed,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.include.is_some()
+            || self.exclude.is_some()
+            || self.where_expr.is_some()
+            || self.format.is_some() && !self.show_unparsed
+    }
+
+    pub fn format(&self) -> Option<&LineFormat> {
+        self.format.as_ref()
+    }
+
+    /// The fields of a line in the --parse format, or as JSON or logfmt without one
+    pub fn fields(&self, line: &[u8]) -> Option<Map<String, Value>> {
+        match &self.format {
+            Some(format) => format.fields(line),
+            None => parse_fields(line),
+        }
+    }
+
+    /// A line passes if it matches any include pattern and no exclude pattern,
+    /// and its fields satisfy the --where expression
+    pub fn matches(&self, line: &[u8]) -> bool {
+        let needs_fields =
+            self.where_expr.is_some() || self.format.is_some() && !self.show_unparsed;
+        let fields = if needs_fields {
+            self.fields(line)
+        } else {
+            None
+        };
+
+        self.matches_fields(line, fields.as_ref())
+    }
+
+    /// Same as `matches`, with the fields of the line already parsed
+    pub fn matches_fields(&self, line: &[u8], fields: Option<&Map<String, Value>>) -> bool {
+        self.include.as_ref().is_none_or(|set| set.is_match(line))
+            && !self.exclude.as_ref().is_some_and(|set| set.is_match(line))
+            && match fields {
+                Some(fields) => self
+                    .where_expr
+                    .as_ref()
+                    .is_none_or(|expr| expr.matches(fields)),
+                // Unparsed lines are shown as they are with --show-unparsed
+                None if self.format.is_some() => self.show_unparsed,
+                None => self.where_expr.is_none(),
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], ignore_case: bool) -> LineFilter {
+        let patterns =
+            |patterns: &[&str]| -> Vec<String> { patterns.iter().map(|p| p.to_string()).collect() };
+        LineFilter::new(
+            &patterns(include),
+            &patterns(exclude),
+            ignore_case,
+            None,
+            None,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn include_any_pattern() {
+        let filter = filter(&["ERROR", "WARN"], &[], false);
+
+        assert!(filter.is_active());
+        assert!(filter.matches(b"ERROR disk full"));
+        assert!(filter.matches(b"WARN slow"));
+        assert!(!filter.matches(b"INFO ok"));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = filter(&["ERROR"], &["health", "ping"], false);
+
+        assert!(filter.matches(b"ERROR disk full"));
+        assert!(!filter.matches(b"ERROR health check"));
+        assert!(!filter.matches(b"INFO ping"));
+
+        let exclude_only = LineFilter::new(&[], &["DEBUG".to_string()], false, None, None, false);
+        assert!(exclude_only.as_ref().unwrap().matches(b"INFO ok"));
+        assert!(!exclude_only.unwrap().matches(b"DEBUG noise"));
+    }
+
+    #[test]
+    fn ignore_case() {
+        assert!(filter(&["error"], &[], true).matches(b"ERROR"));
+        assert!(!filter(&["error"], &[], false).matches(b"ERROR"));
+        assert!(!filter(&[], &["debug"], true).matches(b"DEBUG"));
+    }
+
+    #[test]
+    fn inactive_without_patterns() {
+        let filter = filter(&[], &[], false);
+
+        assert!(!filter.is_active());
+        assert!(filter.matches(b"anything"));
+    }
+
+    #[test]
+    fn where_expression_on_fields() {
+        let filter = LineFilter::new(
+            &[],
+            &[],
+            false,
+            Some(Expr::parse("status >= 500").unwrap()),
+            None,
+            false,
+        )
+        .unwrap();
+
+        assert!(filter.is_active());
+        assert!(filter.matches(br#"{"status": 503}"#));
+        assert!(filter.matches(b"status=500"));
+        assert!(!filter.matches(br#"{"status": 200}"#));
+        // Lines without fields can't satisfy the expression
+        assert!(!filter.matches(b"plain text"));
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(LineFilter::new(&["(".to_string()], &[], false, None, None, false).is_err());
+    }
+}
@@ -1,161 +1,376 @@
-use nix::{errno::Errno, sys::signal, unistd::Pid};
-use notify::{RecommendedWatcher, RecursiveMode, Watcher, event::EventKind, event::ModifyKind};
+use notify::{
+    Event, RecommendedWatcher, RecursiveMode, Watcher, event::EventKind, event::ModifyKind,
+};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
 use std::{
     fs::{File, Metadata},
-    io::{BufReader, Read, Seek, SeekFrom, Write},
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
     os::unix::fs::MetadataExt,
     path::Path,
     path::PathBuf,
-    process::exit,
+    sync::mpsc::{Receiver, RecvTimeoutError, Sender},
     thread,
-    time::Duration,
+    time::Instant,
+};
+
+use crate::rtail::{
+    FileEvent, Printer, StopReason, Timeouts,
+    constants::{CHUNK_SIZE, RECORD_FLUSH_DELAY, REOPEN_RETRY_DELAY},
+    is_broken_pipe,
+    process_watch::ProcessWatch,
 };
 
+/// Everything the follow loop reacts to
+enum FollowEvent {
+    Fs(notify::Result<Event>),
+    ProcessExited(i32),
+    Signal(i32),
+    // A file followed alongside this one has stopped
+    Stop(StopReason),
+}
+
+/// Stops a follow from another thread
+#[derive(Clone)]
+pub struct FollowStopper(Sender<FollowEvent>);
+
+impl FollowStopper {
+    pub fn stop(&self, reason: StopReason) {
+        // A follow that has already ended has nothing to stop
+        let _ = self.0.send(FollowEvent::Stop(reason));
+    }
+}
+
 pub struct FollowFile {
     pub file: File,
     pub reader: BufReader<File>,
     pub position: u64,
     pub starting_len: u64,
-    pub last_line: String,
-    pub line_terminator: char,
     pub file_path: std::path::PathBuf,
     pub follow_name: bool,
-    pub terminate_after_pid: Option<i32>,
+    pub process_watch: Option<ProcessWatch>,
+    pub timeouts: Timeouts,
+    pub printer: Printer,
+    event_tx: Sender<FollowEvent>,
+    // Taken by the follow loop once it starts
+    event_rx: Option<Receiver<FollowEvent>>,
+    // When to look again for a followed name that is missing
+    reopen_at: Option<Instant>,
+    // Whether a missing name has been reported as deleted
+    reported_missing: bool,
 }
 
 impl FollowFile {
     pub fn new(
         file_path: &PathBuf,
-        zero_terminated: bool,
         follow_name: bool,
-        terminate_after_pid: Option<i32>,
+        process_watch: Option<ProcessWatch>,
+        timeouts: Timeouts,
+        printer: Printer,
     ) -> Result<FollowFile, Box<dyn std::error::Error>> {
         let file: File = File::open(file_path)?;
         let starting_len = file.metadata()?.len();
-        let position: u64 = starting_len;
-        let line_terminator: char = if zero_terminated { '\0' } else { '\n' };
-        let last_line: String = String::new();
+        // With fixed-size records, pick up from the start of the incomplete last record
+        let position: u64 = match printer.record_size() {
+            Some(record_size) => starting_len - starting_len % record_size,
+            None => starting_len,
+        };
         let file_path: PathBuf = file_path.to_path_buf();
         let reader = BufReader::new(file.try_clone()?);
+        let (event_tx, event_rx) = std::sync::mpsc::channel();
 
         Ok(FollowFile {
             file,
             reader,
             position,
             starting_len,
-            last_line,
-            line_terminator,
             file_path,
             follow_name,
-            terminate_after_pid,
+            process_watch,
+            timeouts,
+            printer,
+            event_tx,
+            event_rx: Some(event_rx),
+            reopen_at: None,
+            reported_missing: false,
         })
     }
 
-    pub fn follow_file_inotify(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.starting_len > 0 {
-            self.file.seek(SeekFrom::End(-1))?;
-            let mut buffer = [0; 1];
+    /// A handle to stop this follow from another thread
+    pub fn stopper(&self) -> FollowStopper {
+        FollowStopper(self.event_tx.clone())
+    }
+
+    pub fn follow_file_inotify(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        if self.starting_len > 0 && self.printer.record_size().is_none() {
+            let delimiter_len = self.printer.input_delimiter().len() as u64;
+            let mut buffer = vec![0; delimiter_len.min(self.starting_len) as usize];
+            self.file
+                .seek(SeekFrom::Start(self.starting_len - buffer.len() as u64))?;
             self.file.read_exact(&mut buffer)?;
-            let last_char = buffer[0] as char;
-            if last_char != self.line_terminator {
-                println!();
+            if !self.printer.input_delimiter().is_suffix_of(&buffer) {
+                self.printer.notice("")?;
             }
         }
 
         // Start tailing from the end
         self.file.seek(SeekFrom::Start(self.position))?;
-
-        let (tx, rx) = std::sync::mpsc::channel();
-        let mut watcher: RecommendedWatcher = notify::recommended_watcher(tx)?;
-
-        let follow_path = if self.follow_name {
-            self.file_path.parent().unwrap_or(Path::new("."))
+        self.printer.start_follow();
+
+        let tx: Sender<FollowEvent> = self.event_tx.clone();
+        let rx: Receiver<FollowEvent> = self.event_rx.take().ok_or("already following")?;
+        let fs_tx: Sender<FollowEvent> = tx.clone();
+        let mut watcher: RecommendedWatcher =
+            notify::recommended_watcher(move |event_result: notify::Result<Event>| {
+                let _ = fs_tx.send(FollowEvent::Fs(event_result));
+            })?;
+
+        let follow_path: PathBuf = if self.follow_name {
+            self.file_path
+                .parent()
+                .unwrap_or(Path::new("."))
+                .to_path_buf()
         } else {
-            self.file_path.as_path()
+            self.file_path.clone()
         };
 
-        watcher.watch(follow_path, RecursiveMode::NonRecursive)?;
+        watcher.watch(&follow_path, RecursiveMode::NonRecursive)?;
 
-        // If terminate_after_pid is set, spawn a thread to monitor the process
-        if let Some(pid) = self.terminate_after_pid {
-            thread::spawn(move || {
-                check_process_running(pid);
+        // If there are processes to watch, report their deaths through the event loop
+        if let Some(process_watch) = &mut self.process_watch {
+            let pid_tx: Sender<FollowEvent> = tx.clone();
+            process_watch.spawn_monitor(move |pid| {
+                let _ = pid_tx.send(FollowEvent::ProcessExited(pid));
             });
         }
 
+        // Deliver signals through the event loop as well
+        let mut signals = Signals::new([SIGHUP, SIGUSR1, SIGINT, SIGTERM])?;
+        let signal_tx: Sender<FollowEvent> = tx.clone();
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                let _ = signal_tx.send(FollowEvent::Signal(signal));
+            }
+        });
+
         loop {
-            match rx.recv() {
-                Ok(event_result) => {
-                    match event_result {
-                        Ok(event) => {
-                            for path in &event.paths {
-                                if path == &self.file_path {
-                                    match event.kind {
-                                        EventKind::Modify(ModifyKind::Name(_)) => {
-                                            if self.follow_name {
-                                                let metadata = self.file.metadata()?;
-
-                                                // Re-open the file in case it was rotated
-                                                match reopen_file_if_rotated(
-                                                    &self.file_path,
-                                                    &metadata,
-                                                ) {
-                                                    Ok(file_opt) => match file_opt {
-                                                        Some(new_file) => {
-                                                            println!(
-                                                                "File rotated, reopening {:?}",
-                                                                self.file_path
-                                                            );
-                                                            self.file = new_file;
-                                                            self.position = 0;
-                                                            self.reader = BufReader::new(
-                                                                self.file.try_clone()?,
-                                                            );
-                                                        }
-                                                        None => {
-                                                            // No rotation detected, carry on
-                                                        }
-                                                    },
-                                                    Err(e) => {
-                                                        eprintln!(
-                                                            "Error reopening file {:?}: {}",
-                                                            self.file_path, e
-                                                        );
-                                                        continue;
-                                                    }
-                                                };
-
-                                                if let Err(e) = self.process_file_change() {
-                                                    eprintln!(
-                                                        "Error processing file change: {}",
-                                                        e
-                                                    );
-                                                }
-                                            }
-                                        }
-                                        EventKind::Modify(ModifyKind::Data(_)) => {
-                                            if let Err(e) = self.process_file_change() {
-                                                eprintln!("Error processing file change: {}", e);
-                                            }
-                                        }
-
-                                        _ => continue,
+            let event = match self.next_event(&rx) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(reason) = self.check_deadlines()? {
+                        return Ok(reason);
+                    }
+                    // Nothing more arrived for the record being grouped
+                    self.printer.flush_pending_record()?;
+                    self.printer.write_due_mark()?;
+                    if let Some(reason) = self.printer.stop_reason() {
+                        return Ok(reason);
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Watch error: {:?}", e);
+                    continue;
+                }
+            };
+
+            match event {
+                FollowEvent::Fs(Ok(event)) => {
+                    for path in &event.paths {
+                        if path == &self.file_path {
+                            match event.kind {
+                                EventKind::Modify(ModifyKind::Name(_)) => {
+                                    if self.follow_name {
+                                        self.handle_rename()?;
+                                    }
+                                }
+                                EventKind::Modify(ModifyKind::Data(_)) => {
+                                    self.try_process_file_change()?;
+                                }
+                                EventKind::Create(_) => {
+                                    // The missing name is back
+                                    if self.reopen_at.is_some() {
+                                        self.handle_rename()?;
                                     }
                                 }
+
+                                _ => continue,
                             }
                         }
-                        Err(_) => continue,
                     }
                 }
-                Err(e) => eprintln!("Watch error: {:?}", e),
+                FollowEvent::Fs(Err(_)) => continue,
+                FollowEvent::ProcessExited(pid) => {
+                    let should_exit = self
+                        .process_watch
+                        .as_mut()
+                        .is_some_and(|process_watch| process_watch.process_exited(pid));
+
+                    if should_exit {
+                        // Drain anything written before the process died
+                        return self.drain(StopReason::ProcessExited);
+                    }
+                }
+                FollowEvent::Signal(SIGHUP) => {
+                    if !self.file_path.exists() {
+                        eprintln!("File {:?} not found", self.file_path);
+                        continue;
+                    }
+
+                    // A descriptor watch stays on the old inode, so move it over
+                    if self.handle_rename()? && !self.follow_name {
+                        let _ = watcher.unwatch(&follow_path);
+                        watcher.watch(&follow_path, RecursiveMode::NonRecursive)?;
+                    }
+                }
+                FollowEvent::Signal(SIGUSR1) => self.print_status()?,
+                FollowEvent::Signal(_) => {
+                    // SIGINT or SIGTERM
+                    return self.drain(StopReason::Interrupted);
+                }
+                FollowEvent::Stop(reason) => return self.drain(reason),
+            }
+
+            if let Some(reason) = self.printer.stop_reason() {
+                return Ok(reason);
+            }
+            // Events may keep coming without a pause, so deadlines can't wait for one
+            if let Some(reason) = self.check_deadlines()? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Stop if a timeout has expired, and look for a missing file if it is time to
+    fn check_deadlines(&mut self) -> Result<Option<StopReason>, Box<dyn std::error::Error>> {
+        if let Some(reason) = self.timeouts.expired() {
+            return self.drain(reason).map(Some);
+        }
+
+        if self.reopen_at.is_some_and(|at| Instant::now() >= at) {
+            self.handle_rename()?;
+        }
+
+        Ok(None)
+    }
+
+    /// Wait for the next event, but no longer than the nearest deadline
+    fn next_event(&self, rx: &Receiver<FollowEvent>) -> Result<FollowEvent, RecvTimeoutError> {
+        let mut time_left = self.timeouts.time_left();
+        if self.printer.has_pending_record() {
+            time_left = Some(time_left.map_or(RECORD_FLUSH_DELAY, |t| t.min(RECORD_FLUSH_DELAY)));
+        }
+        if let Some(to_mark) = self.printer.time_to_mark() {
+            time_left = Some(time_left.map_or(to_mark, |t| t.min(to_mark)));
+        }
+        if let Some(reopen_at) = self.reopen_at {
+            let to_reopen = reopen_at.saturating_duration_since(Instant::now());
+            time_left = Some(time_left.map_or(to_reopen, |t| t.min(to_reopen)));
+        }
+
+        match time_left {
+            Some(wait) => rx.recv_timeout(wait),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// Reopen the file if the path now points at a new one, returns true if it did.
+    /// If nothing is at the path, it is looked for again from the follow loop.
+    fn handle_rename(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        let metadata = self.file.metadata()?;
+        let mut reopened = false;
+
+        // Re-open the file in case it was rotated
+        match reopen_file_if_rotated(&self.file_path, &metadata) {
+            Ok(file_opt) => match file_opt {
+                Some(new_file) => {
+                    self.reopen_at = None;
+                    self.reported_missing = false;
+                    self.printer.finish()?;
+                    self.printer.file_event(
+                        FileEvent::Rotated,
+                        &format!("File rotated, reopening {:?}", self.file_path),
+                    )?;
+                    self.file = new_file;
+                    self.position = 0;
+                    self.printer.start_input(&self.file)?;
+                    self.reader = BufReader::new(self.file.try_clone()?);
+                    reopened = true;
+                }
+                None => {
+                    // No rotation detected, carry on
+                    self.reopen_at = None;
+                    self.reported_missing = false;
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                // A rotation by renaming leaves the name missing for a moment, so it
+                // only counts as deleted if it is still missing when looked for again
+                let looked_again = self.reopen_at.is_some_and(|at| Instant::now() >= at);
+                if looked_again && !self.reported_missing {
+                    self.reported_missing = true;
+                    self.printer.file_event(
+                        FileEvent::Deleted,
+                        &format!("File {:?} not found", self.file_path),
+                    )?;
+                }
+                self.reopen_at = Some(Instant::now() + REOPEN_RETRY_DELAY);
             }
+            Err(e) => {
+                eprintln!("Error reopening file {:?}: {}", self.file_path, e);
+                return Ok(false);
+            }
+        };
+
+        self.try_process_file_change()?;
+
+        Ok(reopened)
+    }
+
+    /// Read whatever is left, then flush everything before exiting
+    fn drain(&mut self, reason: StopReason) -> Result<StopReason, Box<dyn std::error::Error>> {
+        self.try_process_file_change()?;
+        self.printer.finish()?;
+
+        // Draining may itself have hit a stop condition
+        Ok(self.printer.stop_reason().unwrap_or(reason))
+    }
+
+    fn print_status(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.printer.flush()?;
+        let metadata = self.file.metadata()?;
+
+        eprintln!(
+            "rtail: {}: offset {}, size {}, inode {}, {} bytes emitted",
+            self.file_path.display(),
+            self.position,
+            metadata.len(),
+            metadata.ino(),
+            self.printer.bytes_emitted()
+        );
+
+        Ok(())
+    }
+
+    /// Process a change, reporting errors rather than stopping, unless stdout is gone
+    fn try_process_file_change(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Err(e) = self.process_file_change() {
+            if is_broken_pipe(&*e) {
+                return Err(e);
+            }
+            eprintln!("Error processing file change: {}", e);
         }
+
+        Ok(())
     }
 
-    fn process_file_change<'reader>(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn process_file_change(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         let current_size = self.file.metadata()?.len();
 
-        if current_size == 0 {
+        // An empty file only needs a look if it has been truncated
+        if current_size == 0 && self.position == 0 {
             return Ok(());
         }
 
@@ -164,6 +379,7 @@ impl FollowFile {
         if !res && current_size < self.starting_len {
             // File was truncated
             self.position = 0;
+            self.printer.start_input(&self.file)?;
             self.file.seek(SeekFrom::Start(0))?;
             self.reader = BufReader::new(self.file.try_clone()?);
 
@@ -174,18 +390,36 @@ impl FollowFile {
         Ok(())
     }
 
-    fn handle_modify<'reader>(
-        &mut self,
-        current_size: u64,
-    ) -> Result<bool, Box<dyn std::error::Error>> {
-        let mut buffer = Vec::new();
-        let bytes_read = self.reader.read_to_end(&mut buffer)?;
+    fn handle_modify(&mut self, current_size: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        // Read in chunks, so a long line only takes as much memory as the printer keeps of it
+        let mut buffer = vec![0; CHUNK_SIZE as usize];
+        let mut total_read: u64 = 0;
 
-        if bytes_read == 0 {
+        loop {
+            let bytes_read = match self.reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if total_read == 0 {
+                self.printer.seek(self.position);
+                self.timeouts.data_received();
+            }
+            self.position += bytes_read as u64;
+            total_read += bytes_read as u64;
+
+            // Print only the new bytes
+            self.printer.write(&buffer[..bytes_read])?;
+        }
+
+        if total_read == 0 {
             // File truncated?
             if current_size < self.position {
-                println!("*File truncated*");
-                self.last_line.clear();
+                self.printer.finish()?;
+                self.printer
+                    .file_event(FileEvent::Truncated, "*File truncated*")?;
                 return Ok(false);
             }
 
@@ -193,75 +427,25 @@ impl FollowFile {
             return Ok(true);
         }
 
-        self.position += bytes_read as u64;
-
-        let chunk = String::from_utf8_lossy(&buffer);
-
-        // Append to last_line (incomplete tracking)
-        self.last_line.push_str(&chunk);
-
-        // Print only the new bytes
-        print!("{}", chunk);
-        std::io::stdout().flush()?;
-
-        // Check if last line is complete
-        if self.last_line.ends_with(self.line_terminator) {
-            self.last_line.clear();
-        }
+        self.printer.flush()?;
 
         Ok(true)
     }
 }
 
-fn check_process_running(pid: i32) {
-    loop {
-        match is_process_running(pid) {
-            true => thread::sleep(Duration::from_secs(1)),
-            false => break,
-        }
-    }
-
-    exit(0);
-}
-
-fn is_process_running(pid: i32) -> bool {
-    match signal::kill(Pid::from_raw(pid), None) {
-        Ok(_) => true,
-        Err(Errno::ESRCH) => false,
-        Err(_) => true,
-    }
-}
-
+/// The file now at `file_path` if it is a different one, such as after a rotation
 fn reopen_file_if_rotated(
     file_path: &Path,
     current_metadata: &Metadata,
-) -> Result<Option<File>, Box<dyn std::error::Error>> {
-    // Try to reopen the file and compare inode and device numbers
-    // If they differ, the file was rotated, so return the new file handle
-    // If the file does not exist, keep trying until it does
-
-    let mut first_attempt = true;
-
-    loop {
-        match File::open(file_path) {
-            Ok(f) => {
-                let new_metadata = f.metadata()?;
-
-                if new_metadata.ino() != current_metadata.ino()
-                    || new_metadata.dev() != current_metadata.dev()
-                {
-                    return Ok(Some(f));
-                }
-
-                return Ok(None);
-            }
-            Err(_) => {
-                if first_attempt {
-                    println!("File {:?} not found", file_path);
-                    first_attempt = false;
-                }
-                thread::sleep(Duration::from_secs(1));
-            }
-        }
+) -> std::io::Result<Option<File>> {
+    // Compare inode and device numbers to tell a new file from the same one
+    let file = File::open(file_path)?;
+    let new_metadata = file.metadata()?;
+
+    if new_metadata.ino() != current_metadata.ino() || new_metadata.dev() != current_metadata.dev()
+    {
+        return Ok(Some(file));
     }
+
+    Ok(None)
 }
@@ -0,0 +1,388 @@
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::io::IsTerminal;
+
+const KEY_COLOR: &str = "\x1b[36m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// When to colour JSON keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// Only when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => std::io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Text(String),
+    // A value by its dotted key path, padded to a width
+    Field {
+        path: String,
+        align: char,
+        width: usize,
+    },
+    // Every top-level key the template doesn't mention, as key=value pairs
+    Rest,
+}
+
+/// Output template such as `{ts} {level:>5} {msg} {*}`
+#[derive(Clone, Debug)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl Template {
+    /// Parse a template, where `{{` and `}}` stand for literal braces
+    pub fn parse(input: &str) -> Result<Template, String> {
+        let mut pieces: Vec<Piece> = Vec::new();
+        let mut text = String::new();
+        let mut chars = input.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        spec.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("unclosed '{{' in '{}'", input));
+                    }
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(parse_placeholder(&spec, input)?);
+                }
+                '}' => return Err(format!("unmatched '}}' in '{}'", input)),
+                _ => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+
+        Ok(Template { pieces })
+    }
+
+    fn mentions(&self, key: &str) -> bool {
+        self.pieces.iter().any(|piece| match piece {
+            Piece::Field { path, .. } => path.split('.').next() == Some(key),
+            _ => false,
+        })
+    }
+}
+
+fn parse_placeholder(spec: &str, input: &str) -> Result<Piece, String> {
+    if spec == "*" {
+        return Ok(Piece::Rest);
+    }
+
+    let (path, format) = spec.split_once(':').unwrap_or((spec, ""));
+    if path.is_empty() {
+        return Err(format!("empty placeholder in '{}'", input));
+    }
+
+    let (align, width) = match format.chars().next() {
+        Some(align @ ('<' | '>' | '^')) => (align, &format[1..]),
+        _ => ('<', format),
+    };
+    let width: usize = match width {
+        "" => 0,
+        width => width
+            .parse()
+            .map_err(|_| format!("invalid width '{}' in '{}'", width, input))?,
+    };
+
+    Ok(Piece::Field {
+        path: path.to_string(),
+        align,
+        width,
+    })
+}
+
+/// Renders the fields of each record, from JSON or --parse
+pub struct JsonRenderer {
+    template: Option<Template>,
+    pretty: bool,
+    color: bool,
+    fields: Option<Vec<String>>,
+    drop_fields: Vec<String>,
+}
+
+impl JsonRenderer {
+    pub fn new(
+        template: Option<Template>,
+        pretty: bool,
+        color: bool,
+        fields: Option<Vec<String>>,
+        drop_fields: Vec<String>,
+    ) -> JsonRenderer {
+        JsonRenderer {
+            template,
+            pretty,
+            color,
+            fields,
+            drop_fields,
+        }
+    }
+
+    /// Render the fields of a record with the template, or as JSON
+    pub fn render(&self, fields: Map<String, Value>) -> Vec<u8> {
+        let object = self.project(fields);
+
+        let mut out = String::new();
+        match &self.template {
+            Some(template) => self.write_template(&mut out, template, &object),
+            None => self.write_value(&mut out, &Value::Object(object), 0),
+        }
+
+        out.into_bytes()
+    }
+
+    /// Keep the keys from --fields, in that order, and remove those from --drop-fields
+    fn project(&self, mut object: Map<String, Value>) -> Map<String, Value> {
+        if let Some(fields) = &self.fields {
+            object = fields
+                .iter()
+                .filter_map(|key| object.get(key).map(|value| (key.clone(), value.clone())))
+                .collect();
+        }
+
+        object.retain(|key, _| !self.drop_fields.contains(key));
+        object
+    }
+
+    fn write_template(&self, out: &mut String, template: &Template, object: &Map<String, Value>) {
+        for piece in &template.pieces {
+            match piece {
+                Piece::Text(text) => out.push_str(text),
+                Piece::Field { path, align, width } => {
+                    let text = lookup(object, path).map(value_text).unwrap_or_default();
+                    pad(out, &text, *align, *width);
+                }
+                Piece::Rest => {
+                    let rest = object.iter().filter(|(key, _)| !template.mentions(key));
+                    for (idx, (key, value)) in rest.enumerate() {
+                        if idx > 0 {
+                            out.push(' ');
+                        }
+                        self.write_key(out, key);
+                        out.push('=');
+                        match value {
+                            Value::String(text) if !needs_quotes(text) => out.push_str(text),
+                            _ => out.push_str(&value.to_string()),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_value(&self, out: &mut String, value: &Value, depth: usize) {
+        let (open, close, len) = match value {
+            Value::Object(object) => ('{', '}', object.len()),
+            Value::Array(array) => ('[', ']', array.len()),
+            _ => {
+                out.push_str(&value.to_string());
+                return;
+            }
+        };
+
+        out.push(open);
+        if len == 0 {
+            out.push(close);
+            return;
+        }
+
+        let entries: Vec<(Option<&String>, &Value)> = match value {
+            Value::Object(object) => object.iter().map(|(k, v)| (Some(k), v)).collect(),
+            Value::Array(array) => array.iter().map(|v| (None, v)).collect(),
+            _ => Vec::new(),
+        };
+
+        for (idx, (key, item)) in entries.into_iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            if self.pretty {
+                out.push('\n');
+                out.push_str(&"  ".repeat(depth + 1));
+            }
+            if let Some(key) = key {
+                self.write_key(out, &Value::from(key.as_str()).to_string());
+                out.push_str(if self.pretty { ": " } else { ":" });
+            }
+            self.write_value(out, item, depth + 1);
+        }
+
+        if self.pretty {
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth));
+        }
+        out.push(close);
+    }
+
+    fn write_key(&self, out: &mut String, key: &str) {
+        if self.color {
+            out.push_str(KEY_COLOR);
+            out.push_str(key);
+            out.push_str(RESET_COLOR);
+        } else {
+            out.push_str(key);
+        }
+    }
+}
+
+/// Look up a dotted key path such as `http.status` or `tags.0`
+pub fn lookup<'a>(object: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    let mut parts = path.split('.');
+    let mut value = object.get(parts.next()?)?;
+
+    for part in parts {
+        value = match value {
+            Value::Object(object) => object.get(part)?,
+            Value::Array(array) => array.get(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
+
+/// Strings without their quotes, anything else as compact JSON
+pub fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        _ => value.to_string(),
+    }
+}
+
+/// Strings in key=value pairs are quoted only when they would be ambiguous
+fn needs_quotes(text: &str) -> bool {
+    text.is_empty() || text.contains(|c: char| c.is_whitespace() || c == '"' || c == '=')
+}
+
+fn pad(out: &mut String, text: &str, align: char, width: usize) {
+    let fill = width.saturating_sub(text.chars().count());
+    let (before, after) = match align {
+        '>' => (fill, 0),
+        '^' => (fill / 2, fill - fill / 2),
+        _ => (0, fill),
+    };
+
+    out.extend(std::iter::repeat_n(' ', before));
+    out.push_str(text);
+    out.extend(std::iter::repeat_n(' ', after));
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::rtail::test_util::{output, printer};
+
+    fn render(template: Option<&str>, drop_fields: &[&str], record: Value) -> String {
+        let renderer = JsonRenderer::new(
+            template.map(|template| Template::parse(template).unwrap()),
+            false,
+            false,
+            None,
+            drop_fields.iter().map(|key| key.to_string()).collect(),
+        );
+        let Value::Object(fields) = record else {
+            panic!("records are objects");
+        };
+
+        String::from_utf8(renderer.render(fields)).unwrap()
+    }
+
+    #[test]
+    fn template_padding() {
+        let record = json!({ "level": "info", "n": 7 });
+
+        assert_eq!(render(Some("[{level:>5}]"), &[], record.clone()), "[ info]");
+        assert_eq!(render(Some("[{level:6}]"), &[], record.clone()), "[info  ]");
+        assert_eq!(
+            render(Some("[{level:^8}]"), &[], record.clone()),
+            "[  info  ]"
+        );
+        assert_eq!(render(Some("[{n:<3}] {gone}!"), &[], record), "[7  ] !");
+    }
+
+    #[test]
+    fn template_key_paths() {
+        let record = json!({ "http": { "status": 404 }, "tags": ["a", "b"] });
+
+        assert_eq!(
+            render(Some("{http.status} {tags.1} {http}"), &[], record),
+            r#"404 b {"status":404}"#
+        );
+    }
+
+    #[test]
+    fn template_rest_and_braces() {
+        let record = json!({ "msg": "hi", "user": "ann", "note": "two words", "n": 1 });
+
+        assert_eq!(
+            render(Some("{{{msg}}} {*}"), &[], record),
+            r#"{hi} user=ann note="two words" n=1"#
+        );
+    }
+
+    #[test]
+    fn parse_template_errors() {
+        assert!(Template::parse("{msg").is_err());
+        assert!(Template::parse("msg}").is_err());
+        assert!(Template::parse("{}").is_err());
+        assert!(Template::parse("{msg:>x}").is_err());
+        assert!(Template::parse("{{literal}}").is_ok());
+    }
+
+    #[test]
+    fn drop_fields() {
+        let record = json!({ "msg": "hi", "secret": "x", "n": 1 });
+
+        assert_eq!(
+            render(None, &["secret"], record.clone()),
+            r#"{"msg":"hi","n":1}"#
+        );
+        assert_eq!(render(Some("{*}"), &["secret", "n"], record), "msg=hi");
+    }
+
+    #[test]
+    fn lines_without_json_pass_through() {
+        let mut printer = printer(&["--json", "--format", "{msg}"]);
+
+        assert_eq!(
+            output(&mut printer, b"{\"msg\":\"hi\"}\nplain text\n[1]\n"),
+            "hi\nplain text\n[1]\n"
+        );
+    }
+}
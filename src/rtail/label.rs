@@ -0,0 +1,177 @@
+use clap::ValueEnum;
+use std::path::Path;
+
+// Cycled through so that neighbouring files stand apart
+const LABEL_COLORS: [&str; 6] = [
+    "\x1b[36m", "\x1b[33m", "\x1b[32m", "\x1b[35m", "\x1b[34m", "\x1b[31m",
+];
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// What --prefix labels each file with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PrefixMode {
+    /// The file name without its directory
+    Basename,
+    /// The path as given
+    Path,
+    /// Only the names given with --label
+    Custom,
+}
+
+/// A label for one file, from `--label PATH=NAME`
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub path: String,
+    pub name: String,
+}
+
+impl Label {
+    pub fn parse(input: &str) -> Result<Label, String> {
+        match input.rsplit_once('=') {
+            Some((path, name)) if !path.is_empty() && !name.is_empty() => Ok(Label {
+                path: path.to_string(),
+                name: name.to_string(),
+            }),
+            _ => Err(format!("expected PATH=NAME, got '{}'", input)),
+        }
+    }
+
+    fn matches(&self, file: &str) -> bool {
+        if self.path == file {
+            return true;
+        }
+
+        // The same file given another way, such as ./app.log for app.log
+        match (
+            Path::new(&self.path).canonicalize(),
+            Path::new(file).canonicalize(),
+        ) {
+            (Ok(label_path), Ok(file_path)) => label_path == file_path,
+            _ => false,
+        }
+    }
+}
+
+/// The prefix written before each record of each file: its name in brackets,
+/// padded so the records line up, and coloured per file if `color` is set
+pub fn file_labels(
+    files: &[String],
+    mode: PrefixMode,
+    labels: &[Label],
+    color: bool,
+) -> Result<Vec<Vec<u8>>, String> {
+    let names = files
+        .iter()
+        .map(|file| {
+            if let Some(label) = labels.iter().find(|label| label.matches(file)) {
+                return Ok(label.name.clone());
+            }
+
+            match mode {
+                PrefixMode::Basename => Ok(Path::new(file)
+                    .file_name()
+                    .map_or(file.clone(), |name| name.to_string_lossy().into_owned())),
+                PrefixMode::Path => Ok(file.clone()),
+                PrefixMode::Custom => {
+                    Err(format!("--prefix=custom needs a --label for '{}'", file))
+                }
+            }
+        })
+        .collect::<Result<Vec<String>, String>>()?;
+
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    Ok(names
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let padding = " ".repeat(width - name.chars().count() + 1);
+            if color {
+                let color = LABEL_COLORS[idx % LABEL_COLORS.len()];
+                format!("{}[{}]{}{}", color, name, RESET_COLOR, padding).into_bytes()
+            } else {
+                format!("[{}]{}", name, padding).into_bytes()
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn text(labels: Vec<Vec<u8>>) -> Vec<String> {
+        labels
+            .into_iter()
+            .map(|label| String::from_utf8(label).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_label() {
+        let label = Label::parse("/var/log/a=b=api").unwrap();
+        assert_eq!(
+            (label.path.as_str(), label.name.as_str()),
+            ("/var/log/a=b", "api")
+        );
+
+        assert!(Label::parse("app.log").is_err());
+        assert!(Label::parse("=api").is_err());
+        assert!(Label::parse("app.log=").is_err());
+    }
+
+    #[test]
+    fn labels_are_padded_to_line_up() {
+        let labels = file_labels(
+            &files(&["/var/log/app.log", "logs/db.log"]),
+            PrefixMode::Basename,
+            &[],
+            false,
+        );
+        assert_eq!(text(labels.unwrap()), vec!["[app.log] ", "[db.log]  "]);
+
+        let labels = file_labels(&files(&["a/x", "y"]), PrefixMode::Path, &[], false);
+        assert_eq!(text(labels.unwrap()), vec!["[a/x] ", "[y]   "]);
+    }
+
+    #[test]
+    fn given_labels_take_precedence() {
+        let labels = [Label::parse("db.log=database").unwrap()];
+        let result = file_labels(
+            &files(&["app.log", "db.log"]),
+            PrefixMode::Basename,
+            &labels,
+            false,
+        );
+        assert_eq!(text(result.unwrap()), vec!["[app.log]  ", "[database] "]);
+
+        let result = file_labels(
+            &files(&["app.log", "db.log"]),
+            PrefixMode::Custom,
+            &labels,
+            false,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn colored_labels() {
+        let labels = file_labels(&files(&["a", "b"]), PrefixMode::Basename, &[], true).unwrap();
+        assert_eq!(
+            labels[0],
+            format!("{}[a]{} ", LABEL_COLORS[0], RESET_COLOR).into_bytes()
+        );
+        assert_eq!(
+            labels[1],
+            format!("{}[b]{} ", LABEL_COLORS[1], RESET_COLOR).into_bytes()
+        );
+    }
+}
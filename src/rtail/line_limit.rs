@@ -0,0 +1,197 @@
+use clap::ValueEnum;
+use std::io::Write;
+
+use crate::rtail::delimiter::Delimiter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LongLinePolicy {
+    /// Keep the first NUM bytes and drop the rest of the line
+    Truncate,
+    /// Break the line into pieces of at most NUM bytes
+    Split,
+    /// Drop the whole line
+    Skip,
+}
+
+/// Streaming limiter that caps the length of each line written through it.
+/// Memory use is bounded by `max_len`, no matter how long a line gets.
+pub struct LineLimiter {
+    max_len: u64,
+    policy: LongLinePolicy,
+    delimiter: Delimiter,
+    line_len: u64,
+    pending: Vec<u8>,
+    // End of the last write that may be the first part of a delimiter
+    held: Vec<u8>,
+}
+
+impl LineLimiter {
+    pub fn new(max_len: u64, policy: LongLinePolicy, delimiter: Delimiter) -> LineLimiter {
+        LineLimiter {
+            max_len,
+            policy,
+            delimiter,
+            line_len: 0,
+            pending: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+
+    pub fn max_len(&self) -> u64 {
+        self.max_len
+    }
+
+    pub fn write(&mut self, out: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+        if self.held.is_empty() {
+            return self.write_unheld(out, bytes);
+        }
+
+        let mut joined = std::mem::take(&mut self.held);
+        joined.extend_from_slice(bytes);
+        self.write_unheld(out, &joined)
+    }
+
+    fn write_unheld(&mut self, out: &mut impl Write, mut bytes: &[u8]) -> std::io::Result<()> {
+        while let Some(idx) = self.delimiter.find(bytes) {
+            self.write_segment(out, &bytes[..idx])?;
+            self.end_line(out, true)?;
+            bytes = &bytes[idx + self.delimiter.len()..];
+        }
+
+        // Hold back what could be the start of a delimiter completed by the next write
+        let hold = self.delimiter.partial_suffix_len(bytes);
+        self.write_segment(out, &bytes[..bytes.len() - hold])?;
+        self.held = bytes[bytes.len() - hold..].to_vec();
+
+        Ok(())
+    }
+
+    /// End of input: flush whatever is left of an unterminated line
+    pub fn finish(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        let held = std::mem::take(&mut self.held);
+        self.write_segment(out, &held)?;
+
+        if self.line_len > 0 || !self.pending.is_empty() {
+            self.end_line(out, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_segment(&mut self, out: &mut impl Write, mut segment: &[u8]) -> std::io::Result<()> {
+        match self.policy {
+            LongLinePolicy::Truncate => {
+                let room = self.max_len.saturating_sub(self.line_len);
+                let keep = room.min(segment.len() as u64) as usize;
+                out.write_all(&segment[..keep])?;
+                self.line_len += segment.len() as u64;
+            }
+            LongLinePolicy::Split => {
+                while !segment.is_empty() {
+                    if self.line_len == self.max_len {
+                        out.write_all(self.delimiter.as_bytes())?;
+                        self.line_len = 0;
+                    }
+
+                    let room = self.max_len - self.line_len;
+                    let keep = room.min(segment.len() as u64) as usize;
+                    out.write_all(&segment[..keep])?;
+                    self.line_len += keep as u64;
+                    segment = &segment[keep..];
+                }
+            }
+            LongLinePolicy::Skip => {
+                if self.line_len + segment.len() as u64 <= self.max_len {
+                    self.pending.extend_from_slice(segment);
+                } else if !self.pending.is_empty() {
+                    // Line is too long, stop holding on to it
+                    self.pending = Vec::new();
+                }
+                self.line_len += segment.len() as u64;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end_line(&mut self, out: &mut impl Write, terminated: bool) -> std::io::Result<()> {
+        let dropped = self.line_len.saturating_sub(self.max_len);
+
+        match self.policy {
+            LongLinePolicy::Truncate => {
+                if dropped > 0 {
+                    write!(out, " [... {} bytes truncated]", dropped)?;
+                }
+            }
+            LongLinePolicy::Split => {}
+            LongLinePolicy::Skip => {
+                if dropped > 0 {
+                    write!(out, "[... skipped line of {} bytes]", self.line_len)?;
+                } else {
+                    out.write_all(&self.pending)?;
+                }
+                self.pending.clear();
+            }
+        }
+
+        if terminated {
+            out.write_all(self.delimiter.as_bytes())?;
+        }
+        self.line_len = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(policy: LongLinePolicy, delimiter: &[u8], writes: &[&[u8]]) -> Vec<u8> {
+        let mut limiter = LineLimiter::new(4, policy, Delimiter::new(delimiter));
+        let mut out: Vec<u8> = Vec::new();
+        for bytes in writes {
+            limiter.write(&mut out, bytes).unwrap();
+        }
+        limiter.finish(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        let out = limit(LongLinePolicy::Truncate, b"\n", &[b"abcdefg\nab\n"]);
+        assert_eq!(out, b"abcd [... 3 bytes truncated]\nab\n");
+    }
+
+    #[test]
+    fn splits_long_lines() {
+        let out = limit(LongLinePolicy::Split, b"\n", &[b"abcdefghij\n"]);
+        assert_eq!(out, b"abcd\nefgh\nij\n");
+    }
+
+    #[test]
+    fn skips_long_lines() {
+        let out = limit(LongLinePolicy::Skip, b"\n", &[b"abcdefg\nab\n"]);
+        assert_eq!(out, b"[... skipped line of 7 bytes]\nab\n");
+    }
+
+    #[test]
+    fn delimiter_split_across_writes() {
+        let out = limit(
+            LongLinePolicy::Truncate,
+            b"\r\n",
+            &[b"ab\r", b"\ncd\r", b"\n"],
+        );
+        assert_eq!(out, b"ab\r\ncd\r\n");
+
+        // The held back part of the delimiter doesn't count towards the line length
+        let out = limit(LongLinePolicy::Truncate, b"---", &[b"abcd-", b"-", b"-ef"]);
+        assert_eq!(out, b"abcd---ef");
+    }
+
+    #[test]
+    fn unterminated_partial_delimiter_is_kept() {
+        let out = limit(LongLinePolicy::Split, b"\r\n", &[b"ab\r"]);
+        assert_eq!(out, b"ab\r");
+    }
+}
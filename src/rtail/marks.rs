@@ -0,0 +1,135 @@
+use chrono::Local;
+use std::time::{Duration, Instant};
+
+use crate::rtail::duration::format_duration;
+
+const DIM: &str = "\x1b[2m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// Separators written while following: before the first record after a silence
+/// with --mark-idle, and with the time every so often with --mark-every
+pub struct Marks {
+    idle: Option<Duration>,
+    every: Option<Duration>,
+    color: bool,
+    // Marks are only written once following starts
+    last_record: Option<Instant>,
+    next_mark: Option<Instant>,
+}
+
+impl Marks {
+    pub fn new(idle: Option<Duration>, every: Option<Duration>, color: bool) -> Marks {
+        Marks {
+            idle,
+            every: every.filter(|every| !every.is_zero()),
+            color,
+            last_record: None,
+            next_mark: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        let now = Instant::now();
+        self.last_record = Some(now);
+        self.next_mark = self.every.map(|every| now + every);
+    }
+
+    /// Leave periodic marks to another file's printer, so they aren't repeated
+    pub fn skip_periodic(&mut self) {
+        self.every = None;
+        self.next_mark = None;
+    }
+
+    /// The separator to write before a record arriving now, if it ends a silence
+    pub fn idle_mark(&mut self) -> Option<String> {
+        let now = Instant::now();
+        let silence = now - self.last_record.replace(now)?;
+
+        (silence >= self.idle?)
+            .then(|| self.separator(&format!("{} idle", format_duration(silence))))
+    }
+
+    /// How long until the next periodic mark is due
+    pub fn time_to_mark(&self) -> Option<Duration> {
+        self.next_mark
+            .map(|next_mark| next_mark.saturating_duration_since(Instant::now()))
+    }
+
+    /// The periodic mark, if it is due
+    pub fn due_mark(&mut self) -> Option<String> {
+        let next_mark = self.next_mark?;
+        let now = Instant::now();
+        if now < next_mark {
+            return None;
+        }
+
+        // Marks missed while busy aren't caught up on
+        self.next_mark = self.every.map(|every| {
+            let after = next_mark + every;
+            if after > now { after } else { now + every }
+        });
+        Some(self.separator(&Local::now().format("%Y-%m-%d %H:%M:%S").to_string()))
+    }
+
+    fn separator(&self, text: &str) -> String {
+        if self.color {
+            format!("{}──── {} ────{}", DIM, text, RESET_COLOR)
+        } else {
+            format!("──── {} ────", text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_mark_after_a_silence() {
+        let mut marks = Marks::new(Some(Duration::ZERO), None, false);
+        // Nothing before following starts
+        assert_eq!(marks.idle_mark(), None);
+
+        marks.start();
+        assert!(
+            marks
+                .idle_mark()
+                .is_some_and(|mark| mark.starts_with("──── ") && mark.ends_with(" idle ────"))
+        );
+    }
+
+    #[test]
+    fn no_idle_mark_within_the_limit() {
+        let mut marks = Marks::new(Some(Duration::from_secs(60)), None, false);
+        marks.start();
+
+        assert_eq!(marks.idle_mark(), None);
+        assert_eq!(marks.time_to_mark(), None);
+    }
+
+    #[test]
+    fn periodic_marks() {
+        let mut marks = Marks::new(None, Some(Duration::from_secs(60)), true);
+        assert_eq!(marks.time_to_mark(), None);
+
+        marks.start();
+        assert!(
+            marks
+                .time_to_mark()
+                .is_some_and(|left| left > Duration::from_secs(59))
+        );
+        assert_eq!(marks.due_mark(), None);
+
+        marks.skip_periodic();
+        assert_eq!(marks.time_to_mark(), None);
+    }
+
+    #[test]
+    fn zero_interval_disables_periodic_marks() {
+        let mut marks = Marks::new(None, Some(Duration::ZERO), false);
+        marks.start();
+
+        assert_eq!(marks.time_to_mark(), None);
+        assert_eq!(marks.due_mark(), None);
+    }
+}
@@ -0,0 +1,358 @@
+use chrono::{DateTime, Utc};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{BufWriter, Write},
+    sync::{
+        Arc, Mutex,
+        mpsc::{Receiver, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::rtail::{
+    FollowFile, StopReason, follow_file::FollowStopper, termination::StopConditions,
+};
+
+/// A record held back to be put in time order with those of other files
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MergeRecord {
+    // Records without a timestamp of their own take the one of the record before them
+    pub time: Option<DateTime<Utc>>,
+    // The record as it would have been written, with its label and delimiter
+    pub bytes: Vec<u8>,
+    // The record as it was read, for the stop conditions; None for separators
+    pub record: Option<Vec<u8>>,
+}
+
+// Ordered by time, then by arrival, with when it arrived
+type HeldRecord = (Option<DateTime<Utc>>, u64, Instant, MergeRecord);
+
+/// Writes records from several files in time order, checking the stop conditions
+/// against the merged output rather than each file's
+pub struct Merger<W: Write> {
+    out: BufWriter<W>,
+    stop_conditions: StopConditions,
+    stop_reason: Option<StopReason>,
+}
+
+impl<W: Write> Merger<W> {
+    pub fn new(out: W, stop_conditions: StopConditions) -> Merger<W> {
+        Merger {
+            out: BufWriter::new(out),
+            stop_conditions,
+            stop_reason: None,
+        }
+    }
+
+    /// Why output has stopped, if a stop condition was met
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Write all of `records` in time order, keeping the order of those with the same time
+    pub fn write_sorted(&mut self, mut records: Vec<MergeRecord>) -> std::io::Result<()> {
+        records.sort_by_key(|record| record.time);
+
+        for record in &records {
+            if self.stop_reason.is_some() {
+                break;
+            }
+            self.write_record(record)?;
+        }
+
+        self.out.flush()
+    }
+
+    /// Write records as they arrive, holding each one back for `window` so that
+    /// records from another file with an earlier time can go first; with no window
+    /// they are written in the order they arrive. Returns once every sender is gone
+    /// or a stop condition is met.
+    pub fn write_live(
+        &mut self,
+        records: Receiver<MergeRecord>,
+        window: Duration,
+    ) -> std::io::Result<()> {
+        let mut held: BinaryHeap<Reverse<HeldRecord>> = BinaryHeap::new();
+        let mut arrivals: u64 = 0;
+
+        while self.stop_reason.is_none() {
+            let received = match held.peek() {
+                // Nothing is due until another record arrives
+                None => records.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(Reverse((_, _, arrived, _))) => {
+                    records.recv_timeout(window.saturating_sub(arrived.elapsed()))
+                }
+            };
+
+            match received {
+                Ok(record) => {
+                    arrivals += 1;
+                    held.push(Reverse((record.time, arrivals, Instant::now(), record)));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // Every file is done, so nothing can arrive to go before what is held
+                Err(RecvTimeoutError::Disconnected) => {
+                    while let Some(Reverse((_, _, _, record))) = held.pop() {
+                        if self.stop_reason.is_some() {
+                            break;
+                        }
+                        self.write_record(&record)?;
+                    }
+                    return self.out.flush();
+                }
+            }
+
+            let mut wrote = false;
+            while self.stop_reason.is_none()
+                && held
+                    .peek()
+                    .is_some_and(|Reverse((_, _, arrived, _))| arrived.elapsed() >= window)
+            {
+                if let Some(Reverse((_, _, _, record))) = held.pop() {
+                    self.write_record(&record)?;
+                    wrote = true;
+                }
+            }
+            if wrote {
+                self.out.flush()?;
+            }
+        }
+
+        self.out.flush()
+    }
+
+    fn write_record(&mut self, record: &MergeRecord) -> std::io::Result<()> {
+        self.out.write_all(&record.bytes)?;
+
+        if let Some(original) = &record.record {
+            self.stop_reason = self.stop_conditions.check(original);
+        }
+
+        Ok(())
+    }
+}
+
+/// Follow each file on its own thread while `merger` writes their records, stopping
+/// them all as soon as one stops or the merged output meets a stop condition, and
+/// returning why the first to stop did
+pub fn follow_merged<W: Write>(
+    followers: Vec<FollowFile>,
+    records: Receiver<MergeRecord>,
+    mut merger: Merger<W>,
+    window: Duration,
+) -> Result<StopReason, Box<dyn std::error::Error>> {
+    let stoppers: Vec<FollowStopper> = followers.iter().map(FollowFile::stopper).collect();
+    let first_reason: Arc<Mutex<Option<StopReason>>> = Arc::new(Mutex::new(None));
+
+    let handles: Vec<thread::JoinHandle<Result<(), String>>> = followers
+        .into_iter()
+        .map(|mut follower| {
+            let stoppers = stoppers.clone();
+            let first_reason = Arc::clone(&first_reason);
+            thread::spawn(move || {
+                let reason = follower.follow_file_inotify().map_err(|e| e.to_string())?;
+                stop_all(&stoppers, &first_reason, reason);
+                Ok(())
+            })
+        })
+        .collect();
+
+    let written = merger.write_live(records, window);
+    if let Some(reason) = merger.stop_reason() {
+        stop_all(&stoppers, &first_reason, reason);
+    }
+    // Make sure no follow is left running if writing failed
+    if written.is_err() {
+        stop_all(&stoppers, &first_reason, StopReason::Interrupted);
+    }
+
+    for handle in handles {
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("Error: {}", e),
+            Err(_) => eprintln!("Error: a follow thread panicked"),
+        }
+    }
+    written?;
+
+    let first_reason = *first_reason.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(first_reason.unwrap_or(StopReason::Interrupted))
+}
+
+/// Record `reason` if nothing has stopped yet, and stop every follow
+fn stop_all(
+    stoppers: &[FollowStopper],
+    first_reason: &Mutex<Option<StopReason>>,
+    reason: StopReason,
+) {
+    first_reason
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert(reason);
+
+    for stopper in stoppers {
+        stopper.stop(reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::bytes::Regex;
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn record(secs: Option<i64>, text: &str) -> MergeRecord {
+        MergeRecord {
+            time: secs.and_then(|secs| DateTime::from_timestamp(secs, 0)),
+            bytes: format!("{}\n", text).into_bytes(),
+            record: Some(text.as_bytes().to_vec()),
+        }
+    }
+
+    fn separator(text: &str) -> MergeRecord {
+        MergeRecord {
+            time: None,
+            bytes: format!("{}\n", text).into_bytes(),
+            record: None,
+        }
+    }
+
+    fn sorted(
+        records: Vec<MergeRecord>,
+        stop_conditions: StopConditions,
+    ) -> (String, Option<StopReason>) {
+        let mut out: Vec<u8> = Vec::new();
+        let mut merger = Merger::new(&mut out, stop_conditions);
+        merger.write_sorted(records).unwrap();
+        let stop_reason = merger.stop_reason();
+        drop(merger);
+
+        (String::from_utf8(out).unwrap(), stop_reason)
+    }
+
+    #[test]
+    fn sorted_by_time_keeping_ties_in_order() {
+        let records = vec![
+            record(Some(30), "a3"),
+            record(Some(10), "a1"),
+            record(Some(20), "b2"),
+            record(Some(10), "b1"),
+            record(Some(30), "b3"),
+        ];
+        let (out, stop_reason) = sorted(records, StopConditions::new(None, None));
+
+        assert_eq!(out, "a1\nb1\nb2\na3\nb3\n");
+        assert_eq!(stop_reason, None);
+    }
+
+    #[test]
+    fn records_without_time_go_first() {
+        let records = vec![
+            record(Some(10), "a1"),
+            separator("==> b <=="),
+            record(None, "b0"),
+        ];
+        let (out, _) = sorted(records, StopConditions::new(None, None));
+
+        assert_eq!(out, "==> b <==\nb0\na1\n");
+    }
+
+    #[test]
+    fn sorted_stops_on_merged_output() {
+        let records = vec![
+            record(Some(30), "a3"),
+            record(Some(10), "a1"),
+            record(Some(20), "b2"),
+        ];
+        let (out, stop_reason) = sorted(records, StopConditions::new(None, Some(2)));
+        assert_eq!(out, "a1\nb2\n");
+        assert_eq!(stop_reason, Some(StopReason::MaxLines));
+
+        let records = vec![
+            record(Some(30), "a3"),
+            record(Some(10), "a1"),
+            record(Some(20), "b2"),
+        ];
+        let until = Regex::new("b").unwrap();
+        let (out, stop_reason) = sorted(records, StopConditions::new(Some(until), None));
+        assert_eq!(out, "a1\nb2\n");
+        assert_eq!(stop_reason, Some(StopReason::PatternMatched));
+    }
+
+    #[test]
+    fn separators_do_not_count_as_lines() {
+        let records = vec![
+            separator("----"),
+            record(Some(10), "a1"),
+            record(Some(20), "a2"),
+        ];
+        let (out, _) = sorted(records, StopConditions::new(None, Some(1)));
+
+        assert_eq!(out, "----\na1\n");
+    }
+
+    #[test]
+    fn live_holds_records_back_for_the_window() {
+        let (tx, rx) = mpsc::channel::<MergeRecord>();
+        for record in [
+            record(Some(20), "a2"),
+            record(Some(10), "b1"),
+            record(Some(30), "a3"),
+        ] {
+            tx.send(record).unwrap();
+        }
+        drop(tx);
+
+        let mut out: Vec<u8> = Vec::new();
+        let mut merger = Merger::new(&mut out, StopConditions::new(None, None));
+        merger.write_live(rx, Duration::from_millis(50)).unwrap();
+        drop(merger);
+
+        assert_eq!(String::from_utf8(out).unwrap(), "b1\na2\na3\n");
+    }
+
+    #[test]
+    fn live_without_window_keeps_arrival_order() {
+        let (tx, rx) = mpsc::channel::<MergeRecord>();
+        let sender = thread::spawn(move || {
+            for record in [record(Some(20), "a2"), record(Some(10), "b1")] {
+                tx.send(record).unwrap();
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let mut out: Vec<u8> = Vec::new();
+        let mut merger = Merger::new(&mut out, StopConditions::new(None, None));
+        merger.write_live(rx, Duration::ZERO).unwrap();
+        drop(merger);
+        sender.join().unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "a2\nb1\n");
+    }
+
+    #[test]
+    fn live_stops_on_merged_output() {
+        let (tx, rx) = mpsc::channel::<MergeRecord>();
+        for record in [
+            record(Some(20), "a2"),
+            record(Some(10), "b1"),
+            record(Some(30), "a3"),
+        ] {
+            tx.send(record).unwrap();
+        }
+
+        let mut out: Vec<u8> = Vec::new();
+        let mut merger = Merger::new(&mut out, StopConditions::new(None, Some(2)));
+        // Returns with the sender still open
+        merger.write_live(rx, Duration::from_millis(50)).unwrap();
+        let stop_reason = merger.stop_reason();
+        drop(merger);
+
+        assert_eq!(String::from_utf8(out).unwrap(), "b1\na2\n");
+        assert_eq!(stop_reason, Some(StopReason::MaxLines));
+        drop(tx);
+    }
+}
@@ -1,15 +1,65 @@
 mod args;
+mod arrival;
 mod constants;
+mod context;
+mod delimiter;
+mod duration;
+mod encoding;
+mod event;
+mod expr;
+mod fields;
+mod filter;
 mod follow_file;
+mod json;
+mod label;
+mod line_limit;
+mod marks;
+mod merge;
+mod parse;
+mod printer;
+mod process_watch;
+mod reverse_lines;
+mod reverse_records;
 mod tail_bytes;
+mod tail_chars;
 mod tail_file;
 mod tail_file_by_offset;
+mod tail_range;
+mod tail_records;
+mod tail_reverse;
+mod tail_time;
+mod termination;
+#[cfg(test)]
+mod test_util;
+mod timestamp;
 mod write_std_out;
 
 // Re-export modules
 pub use args::Args;
+pub use arrival::TimestampFormat;
+pub use constants::MERGE_WINDOW;
+pub use delimiter::Delimiter;
+pub use duration::parse_duration;
+pub use encoding::parse_encoding;
+pub use event::{FileEvent, OutputFormat};
+pub use expr::Expr;
+pub use fields::{FieldList, parse_separator};
 pub use follow_file::FollowFile;
-pub use tail_bytes::tail_bytes;
+pub use json::{ColorMode, Template};
+pub use label::{Label, PrefixMode, file_labels};
+pub use line_limit::LongLinePolicy;
+pub use merge::{MergeRecord, Merger, follow_merged};
+pub use parse::LineFormat;
+pub use printer::{Printer, is_broken_pipe};
+pub use process_watch::{PidExitMode, ProcessWatch, WatchedProcess, resolve_pids};
+pub use tail_bytes::{find_byte_offset, tail_bytes};
+pub use tail_chars::tail_chars;
 pub use tail_file::tail_file;
-pub use tail_file_by_offset::offset_tail;
-pub use write_std_out::write_out;
+pub use tail_file_by_offset::{find_line_offset, offset_tail};
+pub use tail_range::{Range, StartPoint, tail_byte_range, tail_from, tail_line_range};
+pub use tail_records::tail_records;
+pub use tail_reverse::tail_reverse;
+pub use tail_time::tail_time_range;
+pub use termination::{StopConditions, StopReason, Timeouts};
+pub use timestamp::{parse_time, parse_time_format, parse_zone};
+pub use write_std_out::{write_header, write_out, write_range};
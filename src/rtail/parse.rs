@@ -0,0 +1,451 @@
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::sync::LazyLock;
+
+// Combined log format, shared by nginx and Apache; the last two fields are optional
+// so the common log format parses too
+static COMBINED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^(\S+) (\S+) (\S+) \[([^\]]+)\] "([^"]*)" (\d{3}) (\S+)(?: "([^"]*)" "([^"]*)")?"#,
+    )
+    .expect("valid combined log regex")
+});
+static SYSLOG_5424: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^<(\d{1,3})>\d{1,2} (\S+) (\S+) (\S+) (\S+) (\S+) (-|(?:\[(?:[^\]\\]|\\.)*\])+) ?(.*)$",
+    )
+    .expect("valid RFC 5424 regex")
+});
+static SYSLOG_3164: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:<(\d{1,3})>)?([A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}|\d{4}-\d{2}-\d{2}T\S+) (\S+) ([^\s:\[]+)(?:\[(\d+)\])?: ?(.*)$",
+    )
+    .expect("valid RFC 3164 regex")
+});
+
+const NGINX_NAMES: [&str; 9] = [
+    "remote_addr",
+    "",
+    "remote_user",
+    "time_local",
+    "request",
+    "status",
+    "body_bytes_sent",
+    "http_referer",
+    "http_user_agent",
+];
+const APACHE_NAMES: [&str; 9] = [
+    "host",
+    "ident",
+    "user",
+    "time",
+    "request",
+    "status",
+    "bytes",
+    "referer",
+    "user_agent",
+];
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// How to turn a line into fields, from --parse
+#[derive(Clone, Debug)]
+pub enum LineFormat {
+    Json,
+    Logfmt,
+    Nginx,
+    Apache,
+    Syslog,
+    // A pattern whose named groups become the fields
+    Regex(Regex),
+}
+
+impl LineFormat {
+    /// Parse `json`, `logfmt`, `nginx`, `apache`, `syslog` or `regex:PATTERN`
+    pub fn parse(input: &str) -> Result<LineFormat, String> {
+        if let Some(pattern) = input.strip_prefix("regex:") {
+            let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+            if regex.capture_names().flatten().next().is_none() {
+                return Err(format!(
+                    "'{}' has no named groups, such as (?P<name>...)",
+                    pattern
+                ));
+            }
+            return Ok(LineFormat::Regex(regex));
+        }
+
+        match input {
+            "json" => Ok(LineFormat::Json),
+            "logfmt" => Ok(LineFormat::Logfmt),
+            "nginx" => Ok(LineFormat::Nginx),
+            "apache" => Ok(LineFormat::Apache),
+            "syslog" => Ok(LineFormat::Syslog),
+            _ => Err(format!(
+                "unknown format '{}', expected json, logfmt, nginx, apache, syslog or regex:PATTERN",
+                input
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineFormat::Json => "json",
+            LineFormat::Logfmt => "logfmt",
+            LineFormat::Nginx => "nginx",
+            LineFormat::Apache => "apache",
+            LineFormat::Syslog => "syslog",
+            LineFormat::Regex(_) => "regex",
+        }
+    }
+
+    /// The fields of a line, or None if it isn't in this format
+    pub fn fields(&self, line: &[u8]) -> Option<Map<String, Value>> {
+        match self {
+            LineFormat::Json => match serde_json::from_slice::<Value>(line) {
+                Ok(Value::Object(object)) => Some(object),
+                _ => None,
+            },
+            LineFormat::Logfmt => parse_logfmt(&String::from_utf8_lossy(line)),
+            LineFormat::Nginx => parse_combined(&String::from_utf8_lossy(line), &NGINX_NAMES),
+            LineFormat::Apache => parse_combined(&String::from_utf8_lossy(line), &APACHE_NAMES),
+            LineFormat::Syslog => parse_syslog(&String::from_utf8_lossy(line)),
+            LineFormat::Regex(regex) => {
+                let line = String::from_utf8_lossy(line);
+                let captures = regex.captures(&line)?;
+                Some(
+                    regex
+                        .capture_names()
+                        .flatten()
+                        .filter_map(|name| {
+                            let value = captures.name(name)?.as_str();
+                            Some((name.to_string(), Value::from(value)))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// The fields of a record: a JSON object, or else logfmt `key=value` pairs
+pub fn parse_fields(line: &[u8]) -> Option<Map<String, Value>> {
+    LineFormat::Json
+        .fields(line)
+        .or_else(|| LineFormat::Logfmt.fields(line))
+}
+
+/// Parse `key=value key2="quoted value" flag` pairs, or None if there are none
+fn parse_logfmt(line: &str) -> Option<Map<String, Value>> {
+    let mut fields: Map<String, Value> = Map::new();
+    let mut rest = line.trim_start();
+    let mut has_pairs = false;
+
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = &rest[key_end..];
+
+        let value = match rest.strip_prefix('=') {
+            Some(after) if after.starts_with('"') => {
+                let (value, after) = unquote(after);
+                rest = after;
+                Value::String(value)
+            }
+            Some(after) => {
+                let value_end = after.find(char::is_whitespace).unwrap_or(after.len());
+                rest = &after[value_end..];
+                Value::String(after[..value_end].to_string())
+            }
+            // A key on its own is a flag
+            None => Value::Bool(true),
+        };
+
+        if !key.is_empty() {
+            has_pairs |= value.is_string();
+            fields.insert(key.to_string(), value);
+        }
+        rest = rest.trim_start();
+    }
+
+    has_pairs.then_some(fields)
+}
+
+/// Read a double-quoted string with backslash escapes, returning it and what follows
+fn unquote(input: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices().skip(1);
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return (value, &input[idx + 1..]),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, escaped)) => value.push(escaped),
+                None => break,
+            },
+            _ => value.push(c),
+        }
+    }
+
+    // No closing quote, so the value runs to the end of the line
+    (value, "")
+}
+
+/// Combined log format, with the request line also split into method, path and protocol
+fn parse_combined(line: &str, names: &[&str; 9]) -> Option<Map<String, Value>> {
+    let captures = COMBINED.captures(line)?;
+    let mut fields: Map<String, Value> = Map::new();
+
+    for (idx, name) in names.iter().enumerate() {
+        let value = match captures.get(idx + 1) {
+            Some(value) if !name.is_empty() && value.as_str() != "-" => value.as_str(),
+            _ => continue,
+        };
+        fields.insert(name.to_string(), number_or_text(value));
+
+        if idx == 4 {
+            let mut parts = value.splitn(3, ' ');
+            for part_name in ["method", "path", "protocol"] {
+                if let Some(part) = parts.next() {
+                    fields.insert(part_name.to_string(), Value::from(part));
+                }
+            }
+        }
+    }
+
+    Some(fields)
+}
+
+/// RFC 5424, or the older RFC 3164 layout used by most syslog files
+fn parse_syslog(line: &str) -> Option<Map<String, Value>> {
+    let mut fields: Map<String, Value> = Map::new();
+
+    let (priority, values) = if let Some(captures) = SYSLOG_5424.captures(line) {
+        let names = [
+            "timestamp",
+            "hostname",
+            "appname",
+            "procid",
+            "msgid",
+            "structured_data",
+            "message",
+        ];
+        let values: Vec<(&str, &str)> = names
+            .iter()
+            .zip(captures.iter().skip(2))
+            .filter_map(|(name, value)| Some((*name, value?.as_str())))
+            .collect();
+        (captures.get(1).map(|m| m.as_str().to_string()), values)
+    } else {
+        let captures = SYSLOG_3164.captures(line)?;
+        let names = ["timestamp", "hostname", "appname", "procid", "message"];
+        let values: Vec<(&str, &str)> = names
+            .iter()
+            .zip(captures.iter().skip(2))
+            .filter_map(|(name, value)| Some((*name, value?.as_str())))
+            .collect();
+        (captures.get(1).map(|m| m.as_str().to_string()), values)
+    };
+
+    if let Some(priority) = priority.and_then(|p| p.parse::<u64>().ok()) {
+        fields.insert("priority".to_string(), Value::from(priority));
+        fields.insert("facility".to_string(), Value::from(priority / 8));
+        fields.insert(
+            "severity".to_string(),
+            Value::from(SEVERITIES[(priority % 8) as usize]),
+        );
+    }
+
+    for (name, value) in values {
+        // RFC 5424 writes - for a missing value
+        if value != "-" || name == "message" {
+            fields.insert(name.to_string(), Value::from(value));
+        }
+    }
+
+    Some(fields)
+}
+
+fn number_or_text(value: &str) -> Value {
+    value
+        .parse::<u64>()
+        .map_or_else(|_| Value::from(value), Value::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtail::test_util::{output, printer};
+
+    fn fields(format: &str, line: &str) -> Option<Value> {
+        LineFormat::parse(format)
+            .unwrap()
+            .fields(line.as_bytes())
+            .map(Value::Object)
+    }
+
+    #[test]
+    fn parse_format_names() {
+        assert_eq!(LineFormat::parse("nginx").unwrap().name(), "nginx");
+        assert_eq!(LineFormat::parse("regex:(?P<a>.)").unwrap().name(), "regex");
+        assert!(LineFormat::parse("csv").is_err());
+        assert!(LineFormat::parse("regex:(.)").is_err());
+        assert!(LineFormat::parse("regex:(?P<a>").is_err());
+    }
+
+    #[test]
+    fn nginx_combined() {
+        let line = r#"10.0.0.1 - - [10/Oct/2024:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 612 "-" "curl/8.0""#;
+        assert_eq!(
+            fields("nginx", line),
+            Some(serde_json::json!({
+                "remote_addr": "10.0.0.1",
+                "time_local": "10/Oct/2024:13:55:36 +0000",
+                "request": "GET /index.html HTTP/1.1",
+                "method": "GET",
+                "path": "/index.html",
+                "protocol": "HTTP/1.1",
+                "status": 200,
+                "body_bytes_sent": 612,
+                "http_user_agent": "curl/8.0",
+            }))
+        );
+        assert_eq!(fields("nginx", "not an access log"), None);
+    }
+
+    #[test]
+    fn apache_common() {
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "POST /form HTTP/1.0" 302 -"#;
+        assert_eq!(
+            fields("apache", line),
+            Some(serde_json::json!({
+                "host": "127.0.0.1",
+                "user": "frank",
+                "time": "10/Oct/2000:13:55:36 -0700",
+                "request": "POST /form HTTP/1.0",
+                "method": "POST",
+                "path": "/form",
+                "protocol": "HTTP/1.0",
+                "status": 302,
+            }))
+        );
+    }
+
+    #[test]
+    fn syslog_rfc3164() {
+        assert_eq!(
+            fields(
+                "syslog",
+                "<34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed"
+            ),
+            Some(serde_json::json!({
+                "priority": 34,
+                "facility": 4,
+                "severity": "crit",
+                "timestamp": "Oct 11 22:14:15",
+                "hostname": "mymachine",
+                "appname": "su",
+                "procid": "123",
+                "message": "'su root' failed",
+            }))
+        );
+        assert_eq!(
+            fields("syslog", "Oct  1 08:00:00 host cron: job done"),
+            Some(serde_json::json!({
+                "timestamp": "Oct  1 08:00:00",
+                "hostname": "host",
+                "appname": "cron",
+                "message": "job done",
+            }))
+        );
+    }
+
+    #[test]
+    fn syslog_rfc5424() {
+        let line = r#"<165>1 2003-10-11T22:14:15.003Z host.example.com evntslog - ID47 [id@32473 iut="3"] An event"#;
+        assert_eq!(
+            fields("syslog", line),
+            Some(serde_json::json!({
+                "priority": 165,
+                "facility": 20,
+                "severity": "notice",
+                "timestamp": "2003-10-11T22:14:15.003Z",
+                "hostname": "host.example.com",
+                "appname": "evntslog",
+                "msgid": "ID47",
+                "structured_data": r#"[id@32473 iut="3"]"#,
+                "message": "An event",
+            }))
+        );
+        assert_eq!(fields("syslog", "plain text"), None);
+    }
+
+    #[test]
+    fn logfmt_pairs() {
+        assert_eq!(
+            fields("logfmt", r#"level=info msg="said \"hi\"\n" cached count=3"#),
+            Some(serde_json::json!({
+                "level": "info",
+                "msg": "said \"hi\"\n",
+                "cached": true,
+                "count": "3",
+            }))
+        );
+        assert_eq!(
+            fields("logfmt", r#"msg="no closing quote"#),
+            Some(serde_json::json!({ "msg": "no closing quote" }))
+        );
+        // Words on their own are only flags, which isn't logfmt
+        assert_eq!(fields("logfmt", "just some words"), None);
+    }
+
+    #[test]
+    fn regex_named_groups() {
+        let format = r"regex:^(?P<level>[A-Z]+) (?P<code>\d+)?";
+        assert_eq!(
+            fields(format, "WARN 42 disk"),
+            Some(serde_json::json!({ "level": "WARN", "code": "42" }))
+        );
+        assert_eq!(
+            fields(format, "WARN disk"),
+            Some(serde_json::json!({ "level": "WARN" }))
+        );
+        assert_eq!(fields(format, "warn"), None);
+    }
+
+    #[test]
+    fn json_or_logfmt_fields() {
+        assert_eq!(
+            parse_fields(br#"{"a": 1}"#).map(Value::Object),
+            Some(serde_json::json!({ "a": 1 }))
+        );
+        assert_eq!(
+            parse_fields(b"a=1").map(Value::Object),
+            Some(serde_json::json!({ "a": "1" }))
+        );
+        assert_eq!(parse_fields(b"[1, 2]"), None);
+    }
+
+    #[test]
+    fn unparsed_lines_are_counted_and_dropped() {
+        let mut printer = printer(&["--parse", "logfmt"]);
+
+        assert_eq!(
+            output(&mut printer, b"a=1\nplain\nb=2\nmore\n"),
+            "a=1\nb=2\n"
+        );
+        assert_eq!(printer.unparsed_count(), 2);
+    }
+
+    #[test]
+    fn show_unparsed_keeps_them() {
+        let mut printer = printer(&["--parse", "logfmt", "--show-unparsed"]);
+
+        assert_eq!(output(&mut printer, b"a=1\nplain\n"), "a=1\nplain\n");
+        assert_eq!(printer.unparsed_count(), 1);
+    }
+}
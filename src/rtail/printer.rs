@@ -0,0 +1,837 @@
+use chrono::{DateTime, Utc};
+use encoding_rs::{Decoder, Encoding, UTF_8};
+use regex::bytes::Regex;
+use std::{
+    fs::File,
+    io::{BufWriter, ErrorKind, Stdout, Write},
+    os::unix::fs::{FileExt, MetadataExt},
+    sync::mpsc::Sender,
+    time::Duration,
+};
+
+use crate::rtail::{
+    Args,
+    arrival::ArrivalStamps,
+    context::{ContextAction, ContextWindow},
+    delimiter::Delimiter,
+    encoding::{detect_encoding, encode_delimiter},
+    event::{FileEvent, OutputFormat, RecordPos, Source, file_event, record_event},
+    fields::Columns,
+    filter::LineFilter,
+    json::JsonRenderer,
+    line_limit::LineLimiter,
+    marks::Marks,
+    merge::MergeRecord,
+    parse::LineFormat,
+    tail_file_by_offset::find_line_offset,
+    termination::{StopConditions, StopReason},
+    timestamp::{Retimer, find_timestamp},
+};
+
+/// Shared output path for both the tail and follow modes
+pub struct Printer {
+    out: BufWriter<CountingWriter<Stdout>>,
+    delimiter: Delimiter,
+    output_delimiter: Vec<u8>,
+    output_format: OutputFormat,
+    source: Source,
+    // Written before each record of the current file with --prefix or --merge
+    label: Vec<u8>,
+    // Arrival times written before followed records with --timestamps or --delta
+    arrival: Option<ArrivalStamps>,
+    // Idle and periodic separators with --mark-idle or --mark-every
+    marks: Option<Marks>,
+    // Rewrites each record's timestamp with --tz or --time-format
+    retimer: Option<Retimer>,
+    // Offset in the current input of the next byte written
+    input_offset: Option<u64>,
+    // Where the next line to be split off starts
+    next_pos: RecordPos,
+    forced_encoding: Option<&'static Encoding>,
+    // Encoding of the current input, which is transcoded to UTF-8 before anything else
+    encoding: &'static Encoding,
+    input_delimiter: Delimiter,
+    decoder: Option<Decoder>,
+    limiter: Option<LineLimiter>,
+    // Per-record processing is only done when some option needs whole records
+    line_mode: bool,
+    partial_line: Vec<u8>,
+    record_size: Option<usize>,
+    hex: bool,
+    record_start: Option<Regex>,
+    header_lines: u64,
+    // Header lines of the current input still to be passed through untouched
+    header_remaining: u64,
+    // Where the data after the header starts in the current file
+    header_end: u64,
+    // Record being grouped from a header line and its continuation lines
+    pending_record: Option<(Vec<u8>, bool, RecordPos)>,
+    scratch: Vec<u8>,
+    filter: LineFilter,
+    columns: Option<Columns>,
+    json: Option<JsonRenderer>,
+    // Lines that weren't in the --parse format
+    unparsed_count: u64,
+    // Where records go instead of stdout with --merge, and the time of the last one
+    merge: Option<Sender<MergeRecord>>,
+    last_time: Option<DateTime<Utc>>,
+    context: ContextWindow,
+    stop_conditions: StopConditions,
+    stop_reason: Option<StopReason>,
+}
+
+impl Printer {
+    pub fn new(args: &Args) -> Result<Printer, Box<dyn std::error::Error>> {
+        let delimiter: Delimiter = args.line_delimiter();
+        let output_delimiter: Vec<u8> = args
+            .output_delimiter
+            .as_ref()
+            .unwrap_or(&delimiter)
+            .as_bytes()
+            .to_vec();
+        let limiter = args
+            .max_line_length
+            .map(|max_len| LineLimiter::new(max_len, args.long_lines, delimiter.clone()));
+        let stop_conditions = StopConditions::new(args.until.clone(), args.max_lines);
+        let filter = LineFilter::new(
+            &args.grep,
+            &args.grep_v,
+            args.ignore_case,
+            args.where_expr.clone(),
+            args.parse.clone(),
+            args.show_unparsed,
+        )?;
+        let header_lines = args.header_lines.unwrap_or(if args.csv { 1 } else { 0 });
+        let structured = args.json || args.format.is_some();
+        if !structured
+            && args
+                .fields
+                .as_ref()
+                .is_some_and(|fields| fields.has_names())
+            && header_lines == 0
+        {
+            return Err("field names need a header line, use --csv or --header-lines".into());
+        }
+        let json = structured.then(|| {
+            JsonRenderer::new(
+                args.format.clone(),
+                args.pretty,
+                args.color.enabled(),
+                args.fields.as_ref().map(|fields| fields.keys().to_vec()),
+                args.drop_fields.clone(),
+            )
+        });
+        let columns = (!structured && (args.fields.is_some() || args.align)).then(|| {
+            let separator = args.field_separator.or(args.csv.then_some(b','));
+            Columns::new(args.fields.clone(), separator, args.align)
+        });
+        let line_mode = stop_conditions.is_active()
+            || header_lines > 0
+            || columns.is_some()
+            || json.is_some()
+            || args.output == OutputFormat::Json
+            || args.prefix_mode().is_some()
+            || args.timestamps.is_some()
+            || args.delta
+            || args.mark_idle.is_some()
+            || args.mark_every.is_some()
+            || args.tz.is_some()
+            || args.time_format.is_some()
+            || filter.is_active()
+            || args.parse.is_some()
+            || args.record_start.is_some()
+            || args.record_size.is_some()
+            || output_delimiter != delimiter.as_bytes();
+        let context = ContextWindow::new(
+            args.before_context.or(args.context).unwrap_or(0),
+            args.after_context.or(args.context).unwrap_or(0),
+        );
+
+        Ok(Printer {
+            out: BufWriter::new(CountingWriter {
+                inner: std::io::stdout(),
+                count: 0,
+            }),
+            input_delimiter: delimiter.clone(),
+            delimiter,
+            output_delimiter,
+            output_format: args.output,
+            source: Source::default(),
+            label: Vec::new(),
+            arrival: (args.timestamps.is_some() || args.delta)
+                .then(|| ArrivalStamps::new(args.timestamps, args.delta)),
+            marks: (args.mark_idle.is_some() || args.mark_every.is_some())
+                .then(|| Marks::new(args.mark_idle, args.mark_every, args.color.enabled())),
+            retimer: (args.tz.is_some() || args.time_format.is_some())
+                .then(|| Retimer::new(args.tz, args.time_format.clone())),
+            input_offset: None,
+            next_pos: RecordPos::default(),
+            forced_encoding: args.encoding,
+            encoding: UTF_8,
+            decoder: None,
+            limiter,
+            line_mode,
+            partial_line: Vec::new(),
+            record_size: args.record_size.map(|n| n as usize),
+            hex: args.hex,
+            record_start: args.record_start.clone(),
+            header_lines,
+            header_remaining: 0,
+            header_end: 0,
+            pending_record: None,
+            scratch: Vec::new(),
+            filter,
+            columns,
+            json,
+            unparsed_count: 0,
+            merge: None,
+            last_time: None,
+            context,
+            stop_conditions,
+            stop_reason: None,
+        })
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if let Some(input_offset) = &mut self.input_offset {
+            *input_offset += bytes.len() as u64;
+        }
+        if self.stop_reason.is_some() {
+            return Ok(());
+        }
+
+        match &mut self.decoder {
+            Some(decoder) => {
+                let mut decoded = String::with_capacity(
+                    decoder
+                        .max_utf8_buffer_length(bytes.len())
+                        .unwrap_or(bytes.len() * 3),
+                );
+                let _ = decoder.decode_to_string(bytes, &mut decoded, false);
+                self.write_decoded(decoded.as_bytes())
+            }
+            None => self.write_decoded(bytes),
+        }
+    }
+
+    fn write_decoded(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match (&mut self.limiter, self.line_mode) {
+            (Some(limiter), false) => limiter.write(&mut self.out, bytes),
+            (None, false) => self.out.write_all(bytes),
+            (Some(limiter), true) => {
+                let mut limited = std::mem::take(&mut self.scratch);
+                limiter.write(&mut limited, bytes)?;
+                self.write_lines(&limited)?;
+                limited.clear();
+                self.scratch = limited;
+                Ok(())
+            }
+            (None, true) => self.write_lines(bytes),
+        }
+    }
+
+    pub fn delimiter(&self) -> &Delimiter {
+        &self.delimiter
+    }
+
+    /// The delimiter as it appears in the input, before transcoding
+    pub fn input_delimiter(&self) -> &Delimiter {
+        &self.input_delimiter
+    }
+
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    pub fn max_line_length(&self) -> Option<u64> {
+        self.limiter.as_ref().map(|limiter| limiter.max_len())
+    }
+
+    pub fn record_size(&self) -> Option<u64> {
+        self.record_size.map(|n| n as u64)
+    }
+
+    pub fn record_start(&self) -> Option<&Regex> {
+        self.record_start.as_ref()
+    }
+
+    pub fn filter(&self) -> &LineFilter {
+        &self.filter
+    }
+
+    pub fn before_context(&self) -> usize {
+        self.context.before()
+    }
+
+    pub fn header_lines(&self) -> u64 {
+        self.header_lines
+    }
+
+    /// Offset where the data after the header lines starts, which tails never go before
+    pub fn header_end(&self) -> u64 {
+        self.header_end
+    }
+
+    pub fn set_header_end(&mut self, offset: u64) {
+        self.header_end = offset;
+    }
+
+    /// Called before output from another file starts
+    pub fn start_file(&mut self, file: &File, name: &str) -> std::io::Result<()> {
+        self.context.reset();
+        self.header_end = 0;
+        self.last_time = None;
+        self.source.name = name.to_string();
+        self.start_input(file)
+    }
+
+    /// Label the records of the current file, in text output
+    pub fn set_label(&mut self, label: &[u8]) {
+        self.label = label.to_vec();
+    }
+
+    /// Start stamping records with their arrival times and marking silences, as following begins
+    pub fn start_follow(&mut self) {
+        if let Some(arrival) = &mut self.arrival {
+            arrival.start();
+        }
+        if let Some(marks) = &mut self.marks {
+            marks.start();
+        }
+    }
+
+    /// Leave --mark-every to another printer following at the same time
+    pub fn skip_periodic_marks(&mut self) {
+        if let Some(marks) = &mut self.marks {
+            marks.skip_periodic();
+        }
+    }
+
+    /// How long until a --mark-every mark is due
+    pub fn time_to_mark(&self) -> Option<Duration> {
+        self.marks.as_ref().and_then(|marks| marks.time_to_mark())
+    }
+
+    /// Write a --mark-every mark if one is due
+    pub fn write_due_mark(&mut self) -> std::io::Result<()> {
+        if self.output_format == OutputFormat::Json {
+            return Ok(());
+        }
+        let Some(mark) = self.marks.as_mut().and_then(|marks| marks.due_mark()) else {
+            return Ok(());
+        };
+
+        let mut line = mark.into_bytes();
+        line.extend_from_slice(&self.output_delimiter);
+        match &self.merge {
+            Some(merge) => {
+                let _ = merge.send(MergeRecord {
+                    time: Some(Utc::now()),
+                    bytes: line,
+                    record: None,
+                });
+            }
+            None => self.out.write_all(&line)?,
+        }
+
+        self.flush()
+    }
+
+    /// Send records to be merged by time instead of writing them; the merger then
+    /// checks the stop conditions
+    pub fn set_merge(&mut self, merge: Sender<MergeRecord>) {
+        self.merge = Some(merge);
+    }
+
+    /// Pick up a file to follow whose start has been printed by another printer,
+    /// reading its header again for the column names without printing it
+    pub fn resume_file(
+        &mut self,
+        file: &File,
+        name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.start_file(file, name)?;
+        self.header_remaining = 0;
+        if self.header_lines == 0 {
+            return Ok(());
+        }
+
+        let header_end: u64 = find_line_offset(file, self.header_lines + 1, self)?;
+        let mut header = vec![0; header_end as usize];
+        file.read_exact_at(&mut header, 0)?;
+        self.header_end = header_end;
+
+        if let Some(columns) = &mut self.columns {
+            let header = self.encoding.decode_with_bom_removal(&header).0;
+            let first_line = match self.delimiter.find(header.as_bytes()) {
+                Some(idx) => &header.as_bytes()[..idx],
+                None => header.as_bytes(),
+            };
+            columns.set_header(first_line);
+        }
+
+        Ok(())
+    }
+
+    /// The next bytes written come from `offset` in the current input
+    pub fn seek(&mut self, offset: u64) {
+        // Carrying on from the previous write keeps the line numbers
+        if self.input_offset == Some(offset) {
+            return;
+        }
+
+        self.input_offset = Some(offset);
+        self.next_pos = RecordPos {
+            offset: Some(offset),
+            line_number: (offset == 0).then_some(1),
+        };
+    }
+
+    /// Same as `seek`, to the start of line `line_number`
+    pub fn seek_line(&mut self, offset: u64, line_number: u64) {
+        self.seek(offset);
+        self.next_pos.line_number = Some(line_number);
+    }
+
+    /// Pick up the encoding of a new input, such as a file reopened after rotation
+    pub fn start_input(&mut self, file: &File) -> std::io::Result<()> {
+        // Fixed-size records are binary, so never transcoded
+        self.encoding = match self.record_size {
+            Some(_) => UTF_8,
+            None => detect_encoding(file, self.forced_encoding)?,
+        };
+        self.input_delimiter = encode_delimiter(&self.delimiter, self.encoding);
+        self.source.inode = file.metadata()?.ino();
+        // A new input starts with its own header
+        self.header_remaining = self.header_lines;
+        self.decoder =
+            (self.encoding != UTF_8).then(|| self.encoding.new_decoder_with_bom_removal());
+
+        Ok(())
+    }
+
+    /// How many records weren't in the --parse format
+    pub fn unparsed_count(&self) -> u64 {
+        self.unparsed_count
+    }
+
+    /// Hand the stop conditions over to a merger, which checks them against its output
+    pub fn take_stop_conditions(&mut self) -> StopConditions {
+        std::mem::replace(&mut self.stop_conditions, StopConditions::new(None, None))
+    }
+
+    /// Why output has stopped, if a stop condition was met
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Write a line of our own, such as a file header, bypassing line processing;
+    /// JSON output has every record's file already, so leaves these out
+    pub fn notice(&mut self, text: &str) -> std::io::Result<()> {
+        if self.output_format == OutputFormat::Json {
+            return Ok(());
+        }
+
+        writeln!(self.out, "{}", text)?;
+        self.flush()
+    }
+
+    /// Report a rotation, truncation or deletion, as `text` or as a JSON event
+    pub fn file_event(&mut self, event: FileEvent, text: &str) -> std::io::Result<()> {
+        if self.output_format == OutputFormat::Text {
+            return self.notice(text);
+        }
+
+        let event = file_event(&self.source, event);
+        self.out.write_all(&event)?;
+        self.out.write_all(b"\n")?;
+        self.flush()
+    }
+
+    /// End of input: emit any partial line state and flush
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(limiter) = &mut self.limiter {
+            if self.line_mode {
+                let mut limited = std::mem::take(&mut self.scratch);
+                limiter.finish(&mut limited)?;
+                self.write_lines(&limited)?;
+                limited.clear();
+                self.scratch = limited;
+            } else {
+                limiter.finish(&mut self.out)?;
+            }
+        }
+
+        if self.record_size.is_some() {
+            // An incomplete fixed-size record is never output
+            self.partial_line.clear();
+        } else if !self.partial_line.is_empty() && self.stop_reason.is_none() {
+            let line = std::mem::take(&mut self.partial_line);
+            self.push_line(&line, false)?;
+        }
+
+        self.flush_pending_record()
+    }
+
+    pub fn has_pending_record(&self) -> bool {
+        self.pending_record.is_some()
+    }
+
+    /// Emit a grouped record without waiting for the next record to start
+    pub fn flush_pending_record(&mut self) -> std::io::Result<()> {
+        if let Some((record, terminated, pos)) = self.pending_record.take()
+            && self.stop_reason.is_none()
+        {
+            self.emit_record(&record, terminated, pos)?;
+        }
+
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Split `bytes` into lines, holding back an unterminated tail until more data arrives
+    fn write_lines(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if let Some(record_size) = self.record_size {
+            return self.write_fixed_records(bytes, record_size);
+        }
+
+        let mut buffer = std::mem::take(&mut self.partial_line);
+
+        // The delimiter may have been split between the held bytes and the new ones
+        let mut search_from = buffer.len().saturating_sub(self.delimiter.len() - 1);
+        let mut line_start = 0;
+        buffer.extend_from_slice(bytes);
+
+        while let Some(idx) = self.delimiter.find(&buffer[search_from..]) {
+            if self.stop_reason.is_some() {
+                return Ok(());
+            }
+
+            let line_end = search_from + idx;
+            self.push_line(&buffer[line_start..line_end], true)?;
+            line_start = line_end + self.delimiter.len();
+            search_from = line_start;
+        }
+
+        if self.stop_reason.is_none() {
+            buffer.drain(..line_start);
+            self.partial_line = buffer;
+        }
+
+        Ok(())
+    }
+
+    /// Split `bytes` into records of `record_size` bytes, holding back an incomplete one
+    fn write_fixed_records(&mut self, bytes: &[u8], record_size: usize) -> std::io::Result<()> {
+        let mut rest = bytes;
+
+        if !self.partial_line.is_empty() {
+            let needed = (record_size - self.partial_line.len()).min(rest.len());
+            self.partial_line.extend_from_slice(&rest[..needed]);
+            rest = &rest[needed..];
+
+            if self.partial_line.len() < record_size {
+                return Ok(());
+            }
+
+            let record = std::mem::take(&mut self.partial_line);
+            let pos = self.advance(record_size);
+            self.emit_record(&record, true, pos)?;
+        }
+
+        let mut records = rest.chunks_exact(record_size);
+        for record in records.by_ref() {
+            if self.stop_reason.is_some() {
+                return Ok(());
+            }
+            let pos = self.advance(record_size);
+            self.emit_record(record, true, pos)?;
+        }
+
+        if self.stop_reason.is_none() {
+            self.partial_line.extend_from_slice(records.remainder());
+        }
+
+        Ok(())
+    }
+
+    /// Group lines into records when --record-start is in use
+    fn push_line(&mut self, line: &[u8], terminated: bool) -> std::io::Result<()> {
+        let pos = self.advance(line.len() + self.delimiter.len());
+
+        if self.header_remaining > 0 {
+            // Header lines skip filtering and don't count towards stop conditions
+            let is_first = self.header_remaining == self.header_lines;
+            self.header_remaining -= 1;
+            let rendered: Vec<u8>;
+            let output: &[u8] = match &mut self.columns {
+                Some(columns) => {
+                    if is_first {
+                        columns.set_header(line);
+                    }
+                    rendered = columns.render(line);
+                    &rendered
+                }
+                None => line,
+            };
+            let mut header: Vec<u8> = Vec::new();
+            if self.output_format == OutputFormat::Json {
+                header.extend_from_slice(&record_event(&self.source, pos, output));
+                header.push(b'\n');
+            } else {
+                header.extend_from_slice(&self.label);
+                header.extend_from_slice(output);
+                if terminated {
+                    header.extend_from_slice(&self.output_delimiter);
+                }
+            }
+
+            // Without a time, merged headers go ahead of every record
+            match &self.merge {
+                Some(merge) => {
+                    let _ = merge.send(MergeRecord {
+                        time: None,
+                        bytes: header,
+                        record: None,
+                    });
+                }
+                None => self.out.write_all(&header)?,
+            }
+            return Ok(());
+        }
+
+        let record_start = match &self.record_start {
+            Some(record_start) => record_start,
+            None => return self.emit_record(line, terminated, pos),
+        };
+
+        if record_start.is_match(line)
+            && let Some((record, record_terminated, record_pos)) = self.pending_record.take()
+        {
+            self.emit_record(&record, record_terminated, record_pos)?;
+        }
+
+        match &mut self.pending_record {
+            Some((record, record_terminated, _)) => {
+                record.extend_from_slice(self.delimiter.as_bytes());
+                record.extend_from_slice(line);
+                *record_terminated = terminated;
+            }
+            None => self.pending_record = Some((line.to_vec(), terminated, pos)),
+        }
+
+        Ok(())
+    }
+
+    /// Where the next record starts, moving past its `len` bytes of input
+    fn advance(&mut self, len: usize) -> RecordPos {
+        let pos = self.next_pos;
+        self.next_pos = RecordPos {
+            offset: pos.offset.map(|offset| offset + len as u64),
+            line_number: pos.line_number.map(|line_number| line_number + 1),
+        };
+
+        // Transcoding and cutting long lines change lengths, so offsets no longer match the file
+        RecordPos {
+            offset: pos
+                .offset
+                .filter(|_| self.decoder.is_none() && self.limiter.is_none()),
+            line_number: pos.line_number.filter(|_| self.limiter.is_none()),
+        }
+    }
+
+    fn emit_record(
+        &mut self,
+        record: &[u8],
+        terminated: bool,
+        pos: RecordPos,
+    ) -> std::io::Result<()> {
+        let is_match = if self.filter.format().is_some() {
+            let fields = self.filter.fields(record);
+            if fields.is_none() {
+                self.unparsed_count += 1;
+            }
+            self.filter.matches_fields(record, fields.as_ref())
+        } else {
+            self.filter.matches(record)
+        };
+
+        let (separator, before) = match self.context.push(record, terminated, pos, is_match) {
+            ContextAction::Skip => return Ok(()),
+            ContextAction::Print { separator, before } => (separator, before),
+        };
+
+        if separator && self.output_format == OutputFormat::Text && self.merge.is_none() {
+            self.out.write_all(b"--")?;
+            self.out.write_all(&self.output_delimiter)?;
+        }
+
+        for (context_record, context_terminated, context_pos) in before {
+            self.output_record(&context_record, context_terminated, context_pos)?;
+            if self.stop_reason.is_some() {
+                return Ok(());
+            }
+        }
+
+        self.output_record(record, terminated, pos)
+    }
+
+    fn output_record(
+        &mut self,
+        record: &[u8],
+        terminated: bool,
+        pos: RecordPos,
+    ) -> std::io::Result<()> {
+        // Merging and stop conditions go by the record as it was
+        let original = record;
+        let retimed: Vec<u8>;
+        let record: &[u8] = match self
+            .retimer
+            .as_ref()
+            .and_then(|retimer| retimer.rewrite(record))
+        {
+            Some(rewritten) => {
+                retimed = rewritten;
+                &retimed
+            }
+            None => record,
+        };
+
+        let rendered: Vec<u8>;
+        let output: &[u8] = match (&mut self.columns, &self.json) {
+            (Some(columns), _) => {
+                rendered = columns.render(record);
+                &rendered
+            }
+            // Records without fields pass through as they are
+            (None, Some(json)) => match self
+                .filter
+                .format()
+                .unwrap_or(&LineFormat::Json)
+                .fields(record)
+            {
+                Some(fields) => {
+                    rendered = json.render(fields);
+                    &rendered
+                }
+                None => record,
+            },
+            (None, None) => record,
+        };
+
+        // Merged records are held back to be put in time order with those of other files
+        let mut merged: Vec<u8> = Vec::new();
+        let out: &mut dyn Write = if self.merge.is_some() {
+            &mut merged
+        } else {
+            &mut self.out
+        };
+
+        if self.output_format == OutputFormat::Text {
+            if let Some(mark) = self.marks.as_mut().and_then(|marks| marks.idle_mark()) {
+                out.write_all(&self.label)?;
+                out.write_all(mark.as_bytes())?;
+                out.write_all(&self.output_delimiter)?;
+            }
+            out.write_all(&self.label)?;
+            if let Some(stamp) = self.arrival.as_mut().and_then(|arrival| arrival.stamp()) {
+                out.write_all(stamp.as_bytes())?;
+            }
+        }
+
+        if self.output_format == OutputFormat::Json {
+            let event = if self.hex {
+                let mut hex: Vec<u8> = Vec::new();
+                write_hex(&mut hex, record)?;
+                hex.pop(); // The newline
+                record_event(&self.source, pos, &hex)
+            } else {
+                record_event(&self.source, pos, output)
+            };
+            out.write_all(&event)?;
+            out.write_all(b"\n")?;
+        } else if self.record_size.is_some() {
+            if self.hex {
+                write_hex(out, record)?;
+            } else {
+                out.write_all(record)?;
+            }
+        } else if self.output_delimiter == self.delimiter.as_bytes() {
+            out.write_all(output)?;
+        } else {
+            // Grouped records still hold input delimiters between their lines
+            let mut rest = output;
+            while let Some(idx) = self.delimiter.find(rest) {
+                out.write_all(&rest[..idx])?;
+                out.write_all(&self.output_delimiter)?;
+                rest = &rest[idx + self.delimiter.len()..];
+            }
+            out.write_all(rest)?;
+        }
+
+        if terminated && self.record_size.is_none() && self.output_format == OutputFormat::Text {
+            out.write_all(&self.output_delimiter)?;
+        }
+
+        if let Some(merge) = &self.merge {
+            let time = find_timestamp(original)
+                .map(|timestamp| timestamp.time)
+                .or(self.last_time);
+            self.last_time = time;
+            // The merger goes away once a stop condition is met, after which nothing is wanted
+            let _ = merge.send(MergeRecord {
+                time,
+                bytes: merged,
+                record: Some(original.to_vec()),
+            });
+        } else {
+            self.stop_reason = self.stop_conditions.check(original);
+        }
+
+        Ok(())
+    }
+
+    /// Number of bytes written to stdout so far
+    pub fn bytes_emitted(&self) -> u64 {
+        self.out.get_ref().count + self.out.buffer().len() as u64
+    }
+}
+
+/// One line of space-separated hex bytes
+fn write_hex(out: &mut dyn Write, record: &[u8]) -> std::io::Result<()> {
+    for (idx, byte) in record.iter().enumerate() {
+        if idx > 0 {
+            out.write_all(b" ")?;
+        }
+        write!(out, "{:02x}", byte)?;
+    }
+
+    out.write_all(b"\n")
+}
+
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// True if the error comes from writing to a closed pipe
+pub fn is_broken_pipe(error: &(dyn std::error::Error + 'static)) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::BrokenPipe)
+}
@@ -0,0 +1,318 @@
+use clap::ValueEnum;
+use nix::{
+    errno::Errno,
+    libc,
+    poll::{PollFd, PollFlags, PollTimeout, poll},
+    sys::signal,
+    unistd::Pid,
+};
+use std::{
+    collections::HashSet,
+    fs,
+    os::fd::{AsFd, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+    thread,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PidExitMode {
+    /// Exit once every watched process has died
+    All,
+    /// Exit as soon as any watched process dies
+    Any,
+}
+
+/// A process to watch, with its pidfd opened as soon as its PID is known
+pub struct WatchedProcess {
+    pub pid: i32,
+    handle: ProcessHandle,
+}
+
+enum ProcessHandle {
+    Pidfd(OwnedFd),
+    // No pidfd support, checked once a second instead
+    Polled,
+    Exited,
+}
+
+impl WatchedProcess {
+    fn open(pid: i32) -> WatchedProcess {
+        let handle = match pidfd_open(pid) {
+            Ok(fd) => ProcessHandle::Pidfd(fd),
+            Err(Errno::ESRCH) => ProcessHandle::Exited,
+            Err(_) => ProcessHandle::Polled,
+        };
+
+        WatchedProcess { pid, handle }
+    }
+
+    fn try_clone(&self) -> std::io::Result<WatchedProcess> {
+        let handle = match &self.handle {
+            ProcessHandle::Pidfd(fd) => ProcessHandle::Pidfd(fd.try_clone()?),
+            ProcessHandle::Polled => ProcessHandle::Polled,
+            ProcessHandle::Exited => ProcessHandle::Exited,
+        };
+
+        Ok(WatchedProcess {
+            pid: self.pid,
+            handle,
+        })
+    }
+}
+
+/// Set of processes whose death ends a follow
+pub struct ProcessWatch {
+    processes: Vec<WatchedProcess>,
+    pub mode: PidExitMode,
+    alive: HashSet<i32>,
+}
+
+impl ProcessWatch {
+    pub fn new(processes: Vec<WatchedProcess>, mode: PidExitMode) -> ProcessWatch {
+        let alive: HashSet<i32> = processes.iter().map(|process| process.pid).collect();
+
+        ProcessWatch {
+            processes,
+            mode,
+            alive,
+        }
+    }
+
+    /// Watch the same processes through duplicates of their pidfds
+    pub fn try_clone(&self) -> std::io::Result<ProcessWatch> {
+        let processes = self
+            .processes
+            .iter()
+            .map(WatchedProcess::try_clone)
+            .collect::<std::io::Result<Vec<WatchedProcess>>>()?;
+
+        Ok(ProcessWatch::new(processes, self.mode))
+    }
+
+    /// Record that `pid` died, returns true when following should stop
+    pub fn process_exited(&mut self, pid: i32) -> bool {
+        self.alive.remove(&pid);
+
+        match self.mode {
+            PidExitMode::All => self.alive.is_empty(),
+            PidExitMode::Any => true,
+        }
+    }
+
+    /// Spawn a thread that calls `on_exit` once for every watched process that dies
+    pub fn spawn_monitor<F>(&mut self, on_exit: F)
+    where
+        F: Fn(i32) + Send + 'static,
+    {
+        let processes = std::mem::take(&mut self.processes);
+
+        thread::spawn(move || {
+            let mut pidfds: Vec<(i32, OwnedFd)> = Vec::new();
+            let mut polled: Vec<i32> = Vec::new();
+
+            for process in processes {
+                match process.handle {
+                    ProcessHandle::Pidfd(fd) => pidfds.push((process.pid, fd)),
+                    ProcessHandle::Polled => polled.push(process.pid),
+                    ProcessHandle::Exited => on_exit(process.pid),
+                }
+            }
+
+            while !pidfds.is_empty() || !polled.is_empty() {
+                let timeout = if polled.is_empty() {
+                    PollTimeout::NONE
+                } else {
+                    PollTimeout::from(1000u16)
+                };
+
+                let mut poll_fds: Vec<PollFd> = pidfds
+                    .iter()
+                    .map(|(_, fd)| PollFd::new(fd.as_fd(), PollFlags::POLLIN))
+                    .collect();
+
+                match poll(&mut poll_fds, timeout) {
+                    Ok(_) | Err(Errno::EINTR) => {}
+                    // Keep watching by checking the processes once a second instead
+                    Err(e) => {
+                        eprintln!("rtail: cannot poll watched processes: {}", e);
+                        drop(poll_fds);
+                        polled.extend(pidfds.drain(..).map(|(pid, _)| pid));
+                        continue;
+                    }
+                }
+
+                // A pidfd becomes readable once its process has exited
+                let exited: Vec<usize> = poll_fds
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, fd)| fd.revents().is_some_and(|r| !r.is_empty()))
+                    .map(|(idx, _)| idx)
+                    .collect();
+                drop(poll_fds);
+
+                for idx in exited.into_iter().rev() {
+                    let (pid, _) = pidfds.remove(idx);
+                    on_exit(pid);
+                }
+
+                polled.retain(|&pid| {
+                    if is_process_running(pid) {
+                        true
+                    } else {
+                        on_exit(pid);
+                        false
+                    }
+                });
+            }
+        });
+    }
+}
+
+/// Collect the processes to watch from --pid, --pid-file and --pid-name, opening
+/// their pidfds right away so that a PID reused later can't be mistaken for them
+pub fn resolve_pids(
+    pids: &[i32],
+    pid_files: &[PathBuf],
+    pid_names: &[String],
+) -> Result<Vec<WatchedProcess>, Box<dyn std::error::Error>> {
+    let mut resolved: Vec<i32> = pids.to_vec();
+
+    for pid_file in pid_files {
+        let contents = fs::read_to_string(pid_file)
+            .map_err(|e| format!("cannot read PID file {:?}: {}", pid_file, e))?;
+        let pid = contents
+            .trim()
+            .parse::<i32>()
+            .map_err(|e| format!("invalid PID in {:?}: {}", pid_file, e))?;
+        resolved.push(pid);
+    }
+
+    for name in pid_names {
+        let matches = find_processes_by_name(name)?;
+        if matches.is_empty() {
+            return Err(format!("no running process named '{}'", name).into());
+        }
+        resolved.extend(matches);
+    }
+
+    resolved.sort_unstable();
+    resolved.dedup();
+
+    Ok(resolved.into_iter().map(WatchedProcess::open).collect())
+}
+
+fn find_processes_by_name(name: &str) -> Result<Vec<i32>, Box<dyn std::error::Error>> {
+    let own_pid = std::process::id() as i32;
+    let mut matches: Vec<i32> = Vec::new();
+
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_string_lossy().parse::<i32>() {
+            Ok(pid) if pid != own_pid => pid,
+            _ => continue,
+        };
+
+        // The process may exit while we look at it, so ignore read errors
+        let comm = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+        let cmdline = fs::read(entry.path().join("cmdline")).unwrap_or_default();
+        let argv0 = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+        let argv0_name = Path::new(&*String::from_utf8_lossy(argv0))
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        if comm.trim_end() == name || argv0_name == name {
+            matches.push(pid);
+        }
+    }
+
+    Ok(matches)
+}
+
+fn pidfd_open(pid: i32) -> Result<OwnedFd, Errno> {
+    // SAFETY: pidfd_open only takes a PID and flags, no pointers into our memory
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+
+    if fd < 0 {
+        return Err(Errno::last());
+    }
+
+    // SAFETY: the call succeeded, so fd is a new descriptor that nothing else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+fn is_process_running(pid: i32) -> bool {
+    match signal::kill(Pid::from_raw(pid), None) {
+        Ok(_) => true,
+        Err(Errno::ESRCH) => false,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    fn watch(pids: &[i32], mode: PidExitMode) -> ProcessWatch {
+        ProcessWatch::new(resolve_pids(pids, &[], &[]).unwrap(), mode)
+    }
+
+    #[test]
+    fn exits_once_all_or_any_have_died() {
+        let mut all = watch(&[1, 2], PidExitMode::All);
+        assert!(!all.process_exited(1));
+        assert!(all.process_exited(2));
+
+        let mut any = watch(&[1, 2], PidExitMode::Any);
+        assert!(any.process_exited(2));
+    }
+
+    #[test]
+    fn reports_processes_that_die() {
+        let mut child = Command::new("sleep").arg("0.1").spawn().unwrap();
+        let pid = child.id() as i32;
+        let mut watch = watch(&[pid], PidExitMode::All);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        watch.spawn_monitor(move |pid| tx.send(pid).unwrap());
+        child.wait().unwrap();
+
+        let exited = rx.recv_timeout(std::time::Duration::from_secs(5));
+        assert_eq!(exited, Ok(pid));
+    }
+
+    #[test]
+    fn reports_processes_already_gone() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id() as i32;
+        child.wait().unwrap();
+        let mut watch = watch(&[pid], PidExitMode::All);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        watch.spawn_monitor(move |pid| tx.send(pid).unwrap());
+
+        let exited = rx.recv_timeout(std::time::Duration::from_secs(5));
+        assert_eq!(exited, Ok(pid));
+    }
+
+    #[test]
+    fn reads_pid_files() {
+        let path = std::env::temp_dir().join(format!("rtail-{}-pid", std::process::id()));
+        let pid_files = [path.clone()];
+        fs::write(&path, "12345\n").unwrap();
+        let resolved = resolve_pids(&[12345, 1], &pid_files, &[]);
+        fs::write(&path, "not a pid").unwrap();
+        let invalid = resolve_pids(&[], &pid_files, &[]);
+        fs::remove_file(&path).unwrap();
+
+        let pids: Vec<i32> = resolved
+            .unwrap()
+            .iter()
+            .map(|process| process.pid)
+            .collect();
+        assert_eq!(pids, vec![1, 12345]);
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::{fs::File, os::unix::fs::FileExt};
+
+use crate::rtail::{constants::CHUNK_SIZE, delimiter::Delimiter};
+
+/// Reads the lines of a file from the end towards the start, in chunks
+pub struct ReverseLines<'a> {
+    file: &'a File,
+    delimiter: Delimiter,
+    max_len: Option<usize>,
+    // File bytes starting at `buffer_pos`, kept contiguous so that a
+    // delimiter split across two chunks is still found
+    buffer: Vec<u8>,
+    buffer_pos: u64,
+    // Where the first line starts, nothing before it is read
+    start_offset: u64,
+    // End of the part of `buffer` not handed out yet
+    end: usize,
+    // File offset where the unfinished line ends, which is past `end` once
+    // a long line has been cut to `max_len`
+    line_end: u64,
+    has_first_line: bool,
+}
+
+impl<'a> ReverseLines<'a> {
+    /// Read lines backwards from `end_offset` down to `start_offset`,
+    /// keeping at most `max_len` bytes of each line
+    pub fn new(
+        file: &'a File,
+        start_offset: u64,
+        end_offset: u64,
+        delimiter: Delimiter,
+        max_len: Option<u64>,
+    ) -> Result<ReverseLines<'a>, Box<dyn std::error::Error>> {
+        let mut scan_end = end_offset;
+
+        // A delimiter at the very end closes the last line rather than starting a new one
+        let delimiter_len = delimiter.len() as u64;
+        if end_offset >= start_offset + delimiter_len {
+            let mut buffer = vec![0; delimiter.len()];
+            file.read_exact_at(&mut buffer, end_offset - delimiter_len)?;
+            if delimiter.is_suffix_of(&buffer) {
+                scan_end -= delimiter_len;
+            }
+        }
+
+        Ok(ReverseLines {
+            file,
+            delimiter,
+            max_len: max_len.map(|n| n as usize),
+            buffer: Vec::new(),
+            buffer_pos: scan_end,
+            start_offset,
+            end: 0,
+            line_end: scan_end,
+            has_first_line: end_offset > start_offset,
+        })
+    }
+
+    /// The previous line and the offset it starts at, without its delimiter
+    pub fn next_line(&mut self) -> std::io::Result<Option<(u64, &[u8])>> {
+        Ok(self
+            .next_line_with_end()?
+            .map(|(line_start, _, line)| (line_start, line)))
+    }
+
+    /// The previous line and the offsets it starts and ends at, without its
+    /// delimiter; the line may be cut short of its end by `max_len`
+    pub fn next_line_with_end(&mut self) -> std::io::Result<Option<(u64, u64, &[u8])>> {
+        loop {
+            if let Some(idx) = self
+                .delimiter
+                .rfind_at(&self.buffer[..self.end], self.buffer_pos)
+            {
+                let line_start = idx + self.delimiter.len();
+                let line_len = self.cap(self.end - line_start);
+                let line_end = self.line_end;
+                self.end = idx;
+                self.line_end = self.buffer_pos + idx as u64;
+
+                return Ok(Some((
+                    self.buffer_pos + line_start as u64,
+                    line_end,
+                    &self.buffer[line_start..line_start + line_len],
+                )));
+            }
+
+            if self.buffer_pos == self.start_offset {
+                // Only the first line of the file is left
+                if !self.has_first_line {
+                    return Ok(None);
+                }
+                self.has_first_line = false;
+                let line_len = self.cap(self.end);
+                return Ok(Some((
+                    self.start_offset,
+                    self.line_end,
+                    &self.buffer[..line_len],
+                )));
+            }
+
+            // Keep the start of the unfinished line, enough of it to still
+            // find a delimiter that began in the previous chunk
+            let keep = self
+                .cap(self.end)
+                .max((self.delimiter.len() - 1).min(self.end));
+            // Reading at least as much as is kept grows an uncut line geometrically,
+            // so it is copied a bounded number of times
+            let read_size =
+                (CHUNK_SIZE.max(keep as u64)).min(self.buffer_pos - self.start_offset) as usize;
+            let mut buffer = vec![0; read_size + keep];
+
+            self.buffer_pos -= read_size as u64;
+            self.file
+                .read_exact_at(&mut buffer[..read_size], self.buffer_pos)?;
+            buffer[read_size..].copy_from_slice(&self.buffer[..keep]);
+            self.buffer = buffer;
+            self.end = read_size + keep;
+        }
+    }
+
+    fn cap(&self, len: usize) -> usize {
+        self.max_len.map_or(len, |max_len| len.min(max_len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtail::test_util::temp_file;
+
+    fn all_lines(file: &File, max_len: Option<u64>) -> Vec<(u64, u64, Vec<u8>)> {
+        let size = file.metadata().unwrap().len();
+        let mut lines = ReverseLines::new(file, 0, size, Delimiter::new(b"\n"), max_len).unwrap();
+        let mut found = Vec::new();
+        while let Some((start, end, line)) = lines.next_line_with_end().unwrap() {
+            found.push((start, end, line.to_vec()));
+        }
+        found
+    }
+
+    #[test]
+    fn lines_last_to_first() {
+        let file = temp_file("reverse", b"one\ntwo\n\nthree\n");
+        assert_eq!(
+            all_lines(&file, None),
+            vec![
+                (9, 14, b"three".to_vec()),
+                (8, 8, b"".to_vec()),
+                (4, 7, b"two".to_vec()),
+                (0, 3, b"one".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_lines_are_cut_but_keep_their_end() {
+        let long = vec![b'x'; CHUNK_SIZE as usize * 3];
+        let file = temp_file("reverse-long", &[b"a\n", &long[..], b"\nb"].concat());
+
+        let lines = all_lines(&file, Some(4));
+        assert_eq!(
+            lines[0],
+            (long.len() as u64 + 3, long.len() as u64 + 4, b"b".to_vec())
+        );
+        assert_eq!(lines[1], (2, long.len() as u64 + 2, b"xxxx".to_vec()));
+        assert_eq!(lines[2], (0, 1, b"a".to_vec()));
+
+        // Without a limit the whole line is returned
+        assert_eq!(all_lines(&file, None)[1].2, long);
+    }
+
+    #[test]
+    fn delimiter_split_across_chunks() {
+        let mut contents = vec![b'x'; CHUNK_SIZE as usize * 2];
+        contents[CHUNK_SIZE as usize - 1..CHUNK_SIZE as usize + 1].copy_from_slice(b"\r\n");
+        let file = temp_file("reverse-split", &contents);
+        let size = contents.len() as u64;
+
+        let mut lines = ReverseLines::new(&file, 0, size, Delimiter::new(b"\r\n"), None).unwrap();
+        assert_eq!(lines.next_line().unwrap().unwrap().0, CHUNK_SIZE + 1);
+        assert_eq!(lines.next_line().unwrap().unwrap().0, 0);
+        assert!(lines.next_line().unwrap().is_none());
+    }
+}
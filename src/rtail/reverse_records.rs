@@ -0,0 +1,84 @@
+use encoding_rs::{Encoding, UTF_8};
+use regex::bytes::Regex;
+use std::fs::File;
+
+use crate::rtail::{
+    Printer, delimiter::Delimiter, encoding::decode_line, reverse_lines::ReverseLines,
+};
+
+/// Reads records backwards, where a record is a line matching `record_start`
+/// together with the continuation lines that follow it
+pub struct ReverseRecords<'a> {
+    lines: ReverseLines<'a>,
+    delimiter: Delimiter,
+    record_start: Option<Regex>,
+    // Lines are transcoded to UTF-8 before being matched or joined
+    encoding: &'static Encoding,
+    record: Vec<u8>,
+}
+
+impl<'a> ReverseRecords<'a> {
+    /// Read records backwards from `end_offset`, split and grouped the way `printer` would
+    pub fn new(
+        file: &'a File,
+        end_offset: u64,
+        printer: &Printer,
+    ) -> Result<ReverseRecords<'a>, Box<dyn std::error::Error>> {
+        Ok(ReverseRecords {
+            lines: ReverseLines::new(
+                file,
+                0,
+                end_offset,
+                printer.input_delimiter().clone(),
+                printer.max_line_length(),
+            )?,
+            delimiter: printer.delimiter().clone(),
+            record_start: printer.record_start().cloned(),
+            encoding: printer.encoding(),
+            record: Vec::new(),
+        })
+    }
+
+    /// The previous record and the offset it starts at, without its final delimiter
+    pub fn next_record(&mut self) -> std::io::Result<Option<(u64, &[u8])>> {
+        let record_start = match &self.record_start {
+            Some(record_start) => record_start,
+            None if self.encoding == UTF_8 => return self.lines.next_line(),
+            None => {
+                return Ok(match self.lines.next_line()? {
+                    Some((line_offset, line)) => {
+                        self.record = decode_line(self.encoding, line).into_owned();
+                        Some((line_offset, &self.record))
+                    }
+                    None => None,
+                });
+            }
+        };
+
+        self.record.clear();
+        let mut has_lines = false;
+
+        while let Some((line_offset, line)) = self.lines.next_line()? {
+            let line = decode_line(self.encoding, line);
+            // Lines arrive last to first, so each one goes in front
+            let mut joined = line.to_vec();
+            if has_lines {
+                joined.extend_from_slice(self.delimiter.as_bytes());
+                joined.extend_from_slice(&self.record);
+            }
+            self.record = joined;
+            has_lines = true;
+
+            if record_start.is_match(&line) {
+                return Ok(Some((line_offset, &self.record)));
+            }
+        }
+
+        // Continuation lines at the very start of the file form a record of their own
+        if has_lines {
+            return Ok(Some((0, &self.record)));
+        }
+
+        Ok(None)
+    }
+}
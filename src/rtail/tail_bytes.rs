@@ -1,14 +1,34 @@
 use std::fs::File;
 
-use crate::rtail::write_out;
+use crate::rtail::{
+    Printer, encoding::code_unit_size, tail_chars::align_to_char_boundary, write_out,
+};
 
 pub fn tail_bytes(
     file: &mut File,
     num_bytes: u64,
     is_plus: bool,
+    align_utf8: bool,
+    printer: &mut Printer,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let start_pos: u64 = find_byte_offset(file, num_bytes, is_plus, align_utf8, printer)?;
+
+    // Read and print the rest of the file from the start_offset
+    write_out(file, start_pos, printer)?;
+
+    Ok(())
+}
+
+/// Offset to start printing from for `-c NUM` or `-c +NUM`
+pub fn find_byte_offset(
+    file: &File,
+    num_bytes: u64,
+    is_plus: bool,
+    align_utf8: bool,
+    printer: &Printer,
+) -> std::io::Result<u64> {
     let file_size: u64 = file.metadata()?.len();
-    let start_pos: u64 = if num_bytes > file_size {
+    let mut start_pos: u64 = if num_bytes > file_size {
         0
     } else {
         if is_plus {
@@ -18,8 +38,13 @@ pub fn tail_bytes(
         }
     };
 
-    // Read and print the rest of the file from the start_offset
-    write_out(file, start_pos)?;
+    // Never start in the middle of a UTF-16 code unit
+    start_pos = start_pos.next_multiple_of(code_unit_size(printer.encoding()) as u64);
 
-    Ok(())
+    if align_utf8 {
+        // Don't start in the middle of a multibyte character
+        start_pos = align_to_char_boundary(file, start_pos)?;
+    }
+
+    Ok(start_pos)
 }
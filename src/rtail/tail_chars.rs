@@ -0,0 +1,402 @@
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use memchr::memrchr;
+use std::{fs::File, os::unix::fs::FileExt};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::rtail::{Printer, constants::CHUNK_SIZE, encoding::bom_len, write_out};
+
+pub fn tail_chars(
+    file: &mut File,
+    num_chars: u64,
+    is_plus: bool,
+    graphemes: bool,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let encoding = printer.encoding();
+    if graphemes && encoding != UTF_8 {
+        return Err(format!("--graphemes needs UTF-8 input, not {}", encoding.name()).into());
+    }
+
+    let start_pos: u64 = match (is_plus, graphemes) {
+        (false, false) => last_chars_offset(file, num_chars, CharStarts::new(encoding)?)?,
+        (true, false) => nth_char_offset(file, num_chars, CharStarts::new(encoding)?)?,
+        (false, true) => last_graphemes_offset(file, num_chars)?,
+        (true, true) => nth_grapheme_offset(file, num_chars)?,
+    };
+
+    // Read and print the rest of the file from the start_offset
+    write_out(file, start_pos, printer)?;
+
+    Ok(())
+}
+
+/// Move `offset` forward past any UTF-8 continuation bytes, onto the next character boundary
+pub fn align_to_char_boundary(file: &File, offset: u64) -> std::io::Result<u64> {
+    let mut buffer = [0; 4];
+    let bytes_read = file.read_at(&mut buffer, offset)?;
+    let skip = buffer[..bytes_read]
+        .iter()
+        .take(3)
+        .take_while(|&&b| is_continuation_byte(b))
+        .count();
+
+    Ok(offset + skip as u64)
+}
+
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// How to tell where characters start in the raw bytes of an encoding
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CharStarts {
+    // Every byte that is not a continuation byte
+    Utf8,
+    // Every 16-bit unit that is not the low half of a surrogate pair
+    Utf16 { big_endian: bool },
+    // Every byte
+    SingleByte,
+}
+
+impl CharStarts {
+    fn new(encoding: &'static Encoding) -> Result<CharStarts, String> {
+        if encoding == UTF_8 {
+            Ok(CharStarts::Utf8)
+        } else if encoding == UTF_16LE || encoding == UTF_16BE {
+            Ok(CharStarts::Utf16 {
+                big_endian: encoding == UTF_16BE,
+            })
+        } else if encoding.is_single_byte() {
+            Ok(CharStarts::SingleByte)
+        } else {
+            Err(format!(
+                "--chars can't count characters in {}",
+                encoding.name()
+            ))
+        }
+    }
+
+    /// Size in bytes of the units characters start on
+    fn unit(self) -> usize {
+        match self {
+            CharStarts::Utf16 { .. } => 2,
+            CharStarts::Utf8 | CharStarts::SingleByte => 1,
+        }
+    }
+
+    fn is_start(self, unit: &[u8]) -> bool {
+        match self {
+            CharStarts::Utf8 => !is_continuation_byte(unit[0]),
+            CharStarts::Utf16 { big_endian } => {
+                let high_byte = if big_endian { unit[0] } else { unit[1] };
+                !(0xDC..=0xDF).contains(&high_byte)
+            }
+            CharStarts::SingleByte => true,
+        }
+    }
+}
+
+/// Where the characters start and end in a file, leaving out a byte order mark
+/// and, for UTF-16, an odd byte at the end
+fn char_data(file: &File, starts: CharStarts) -> std::io::Result<(u64, u64)> {
+    let file_size: u64 = file.metadata()?.len();
+    let data_start = bom_len(file)?;
+    let data_end = file_size - file_size.saturating_sub(data_start) % starts.unit() as u64;
+
+    Ok((data_start.min(data_end), data_end))
+}
+
+/// Offset of the N-th character from the end
+fn last_chars_offset(file: &File, num_chars: u64, starts: CharStarts) -> std::io::Result<u64> {
+    let (data_start, data_end) = char_data(file, starts)?;
+    let mut pos: u64 = data_end;
+    let mut char_count: u64 = 0;
+    let mut chunk_buffer: Vec<u8> = vec![0; CHUNK_SIZE as usize];
+
+    if num_chars == 0 {
+        return Ok(file.metadata()?.len());
+    }
+
+    // CHUNK_SIZE is a whole number of units, so every chunk stays aligned to them
+    while pos > data_start {
+        let read_size = CHUNK_SIZE.min(pos - data_start);
+        pos -= read_size;
+        let chunk = &mut chunk_buffer[..read_size as usize];
+        file.read_exact_at(chunk, pos)?;
+
+        for (idx, unit) in chunk.chunks_exact(starts.unit()).enumerate().rev() {
+            if starts.is_start(unit) {
+                char_count += 1;
+                if char_count == num_chars {
+                    return Ok(pos + (idx * starts.unit()) as u64);
+                }
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+/// Offset of character N, counting from 1
+fn nth_char_offset(file: &File, start_char: u64, starts: CharStarts) -> std::io::Result<u64> {
+    let (data_start, data_end) = char_data(file, starts)?;
+    let mut pos: u64 = data_start;
+    let mut char_count: u64 = 0;
+    let mut chunk_buffer: Vec<u8> = vec![0; CHUNK_SIZE as usize];
+
+    if start_char <= 1 {
+        return Ok(0);
+    }
+
+    while pos < data_end {
+        let read_size = CHUNK_SIZE.min(data_end - pos);
+        let chunk = &mut chunk_buffer[..read_size as usize];
+        file.read_exact_at(chunk, pos)?;
+
+        for (idx, unit) in chunk.chunks_exact(starts.unit()).enumerate() {
+            if starts.is_start(unit) {
+                char_count += 1;
+                if char_count == start_char {
+                    return Ok(pos + (idx * starts.unit()) as u64);
+                }
+            }
+        }
+
+        pos += read_size;
+    }
+
+    Ok(file.metadata()?.len())
+}
+
+/// Offset of the N-th grapheme cluster from the end, segmenting the file in windows
+/// from the end backwards
+fn last_graphemes_offset(file: &File, num_graphemes: u64) -> std::io::Result<u64> {
+    let mut window_end: u64 = file.metadata()?.len();
+    let mut grapheme_count: u64 = 0;
+
+    if num_graphemes == 0 {
+        return Ok(window_end);
+    }
+
+    while window_end > 0 {
+        let window_start = match window_end.saturating_sub(CHUNK_SIZE) {
+            0 => 0,
+            start => align_to_char_boundary(file, start)?,
+        };
+        let mut window = vec![0; (window_end - window_start) as usize];
+        file.read_exact_at(&mut window, window_start)?;
+
+        let (starts, window_before_end) = whole_graphemes_to_end(&window, window_start == 0);
+        for &start in starts.iter().rev() {
+            grapheme_count += 1;
+            if grapheme_count == num_graphemes {
+                return Ok(window_start + start as u64);
+            }
+        }
+
+        window_end = window_start + window_before_end as u64;
+    }
+
+    Ok(0)
+}
+
+/// Offset of grapheme cluster N, counting from 1, segmenting the file in windows
+/// from the start forwards
+fn nth_grapheme_offset(file: &File, start_grapheme: u64) -> std::io::Result<u64> {
+    let file_size: u64 = file.metadata()?.len();
+    let mut window_start: u64 = 0;
+    let mut grapheme_count: u64 = 0;
+
+    if start_grapheme <= 1 {
+        return Ok(0);
+    }
+
+    while window_start < file_size {
+        let window_end = align_to_char_boundary(file, (window_start + CHUNK_SIZE).min(file_size))?;
+        let mut window = vec![0; (window_end - window_start) as usize];
+        file.read_exact_at(&mut window, window_start)?;
+
+        let (starts, next_start) = whole_graphemes_from_start(&window, window_end == file_size);
+        for &start in &starts {
+            grapheme_count += 1;
+            if grapheme_count == start_grapheme {
+                return Ok(window_start + start as u64);
+            }
+        }
+
+        window_start += next_start as u64;
+    }
+
+    Ok(file_size)
+}
+
+/// Starts of the clusters that end a window, leaving out those the window may start in
+/// the middle of, and where the window before it should end. No rule looks back past an
+/// ASCII character other than CR, so segmenting from one gets the same boundaries after
+/// its cluster as segmenting the whole file.
+fn whole_graphemes_to_end(window: &[u8], at_file_start: bool) -> (Vec<usize>, usize) {
+    if at_file_start {
+        return (grapheme_starts(window), 0);
+    }
+
+    let safe = window
+        .iter()
+        .position(|&byte| byte.is_ascii() && byte != b'\r')
+        .unwrap_or(0);
+    let starts: Vec<usize> = grapheme_starts(&window[safe..])
+        .into_iter()
+        .skip(1)
+        .map(|start| safe + start)
+        .collect();
+
+    // A cluster longer than the window is passed over
+    let window_before_end = starts.first().copied().unwrap_or(safe);
+    (starts, window_before_end)
+}
+
+/// Starts of the clusters that begin a window, leaving out the one the window may end
+/// in the middle of, and where the next window should start
+fn whole_graphemes_from_start(window: &[u8], at_file_end: bool) -> (Vec<usize>, usize) {
+    if at_file_end {
+        return (grapheme_starts(window), window.len());
+    }
+
+    if let Some(idx) = memrchr(b'\n', window) {
+        return (grapheme_starts(&window[..=idx]), idx + 1);
+    }
+
+    // A cluster longer than the window is split where the window ends
+    let mut starts = grapheme_starts(window);
+    match starts.len() {
+        0 | 1 => (starts, window.len()),
+        _ => {
+            let next_start = starts.pop().unwrap_or(window.len());
+            (starts, next_start)
+        }
+    }
+}
+
+/// Byte offsets where grapheme clusters start, each invalid byte counting as one cluster
+fn grapheme_starts(bytes: &[u8]) -> Vec<usize> {
+    let mut starts: Vec<usize> = Vec::new();
+    let mut base: usize = 0;
+
+    for chunk in bytes.utf8_chunks() {
+        starts.extend(
+            chunk
+                .valid()
+                .grapheme_indices(true)
+                .map(|(idx, _)| base + idx),
+        );
+        base += chunk.valid().len();
+
+        starts.extend(base..base + chunk.invalid().len());
+        base += chunk.invalid().len();
+    }
+
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::WINDOWS_1252;
+
+    use super::*;
+    use crate::rtail::test_util::temp_file;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn utf8_char_offsets() {
+        let file = temp_file("utf8-chars", "aé€😀b".as_bytes());
+        let starts = CharStarts::new(UTF_8).unwrap();
+
+        assert_eq!(last_chars_offset(&file, 1, starts).unwrap(), 10);
+        assert_eq!(last_chars_offset(&file, 2, starts).unwrap(), 6);
+        assert_eq!(last_chars_offset(&file, 9, starts).unwrap(), 0);
+        assert_eq!(nth_char_offset(&file, 3, starts).unwrap(), 3);
+        assert_eq!(nth_char_offset(&file, 9, starts).unwrap(), 11);
+    }
+
+    #[test]
+    fn grapheme_starts_keep_clusters_whole() {
+        // e + combining acute, a family joined with ZWJs, and a flag
+        let text = "e\u{301}👨\u{200d}👩\u{200d}👧🇩🇪x";
+        assert_eq!(grapheme_starts(text.as_bytes()), vec![0, 3, 21, 29]);
+        assert_eq!(grapheme_starts(b"a\xffb"), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn backward_window_drops_the_cluster_it_may_start_in() {
+        // The window may start in the middle of a flag, so counting starts after the a
+        let window = "🇪a🇩🇪\u{301}b".as_bytes();
+        let (starts, window_before_end) = whole_graphemes_to_end(window, false);
+
+        assert_eq!(starts, vec![5, 15]);
+        assert_eq!(window_before_end, 5);
+        assert_eq!(whole_graphemes_to_end(window, true).0, vec![0, 4, 5, 15]);
+    }
+
+    #[test]
+    fn forward_window_ends_at_the_last_newline() {
+        let window = "ab\ncd\u{301}".as_bytes();
+        assert_eq!(
+            whole_graphemes_from_start(window, false),
+            (vec![0, 1, 2], 3)
+        );
+        assert_eq!(
+            whole_graphemes_from_start(window, true),
+            (vec![0, 1, 2, 3, 4], 7)
+        );
+
+        // Without a newline the last cluster may go on into the next window
+        let window = "abc\u{301}".as_bytes();
+        assert_eq!(whole_graphemes_from_start(window, false), (vec![0, 1], 2));
+    }
+
+    #[test]
+    fn grapheme_offsets_across_windows() {
+        let chunk = CHUNK_SIZE as usize;
+        // A cluster of e and combining accents straddles the first window's end
+        let mut contents = vec![b'a'; chunk - 3];
+        contents.extend_from_slice("e\u{301}\u{301}\u{301}".as_bytes());
+        contents.extend_from_slice(b"zz");
+        let file = temp_file("graphemes", &contents);
+
+        assert_eq!(last_graphemes_offset(&file, 3).unwrap(), chunk as u64 - 3);
+        assert_eq!(last_graphemes_offset(&file, 4).unwrap(), chunk as u64 - 4);
+        assert_eq!(
+            nth_grapheme_offset(&file, chunk as u64 - 2).unwrap(),
+            chunk as u64 - 3
+        );
+        assert_eq!(
+            nth_grapheme_offset(&file, chunk as u64 - 1).unwrap(),
+            chunk as u64 + 4
+        );
+    }
+
+    #[test]
+    fn utf16_char_offsets_skip_surrogates_and_bom() {
+        let mut contents = vec![0xFF, 0xFE];
+        contents.extend(utf16le("a😀b"));
+        let file = temp_file("utf16-chars", &contents);
+        let starts = CharStarts::new(UTF_16LE).unwrap();
+
+        assert_eq!(last_chars_offset(&file, 1, starts).unwrap(), 8);
+        assert_eq!(last_chars_offset(&file, 2, starts).unwrap(), 4);
+        assert_eq!(last_chars_offset(&file, 3, starts).unwrap(), 2);
+        assert_eq!(nth_char_offset(&file, 2, starts).unwrap(), 4);
+        assert_eq!(nth_char_offset(&file, 3, starts).unwrap(), 8);
+    }
+
+    #[test]
+    fn single_byte_char_offsets() {
+        let file = temp_file("latin1-chars", b"caf\xe9!");
+        let starts = CharStarts::new(WINDOWS_1252).unwrap();
+
+        assert_eq!(starts, CharStarts::SingleByte);
+        assert_eq!(last_chars_offset(&file, 2, starts).unwrap(), 3);
+    }
+}
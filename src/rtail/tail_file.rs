@@ -1,35 +1,72 @@
-use memchr::memrchr_iter;
 use std::{fs::File, os::unix::fs::FileExt};
 
-use crate::rtail::{constants::CHUNK_SIZE, write_out};
+use crate::rtail::{
+    Printer, constants::CHUNK_SIZE, delimiter::Delimiter, reverse_records::ReverseRecords,
+    write_out,
+};
 
 pub fn tail_file(
     file: &mut File,
     num_lines: u64,
-    zero_terminated: bool,
+    printer: &mut Printer,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut pos: u64 = file.metadata()?.len();
+    if num_lines == 0 {
+        // Nothing to print
+        return Ok(());
+    }
+
+    if printer.filter().is_active() || printer.record_start().is_some() {
+        // Only matching records count, so look at each one on the way back
+        let line_offset = find_last_matches(file, num_lines, printer)?;
+        write_out(file, line_offset, printer)?;
+
+        return Ok(());
+    }
+
+    let line_offset = find_last_lines_offset(file, num_lines, printer)?;
+
+    // Write from line_offset to end of file
+    write_out(file, line_offset, printer)?;
+
+    Ok(())
+}
+
+/// Offset of the start of the N-th line from the end, or 0 if there are fewer
+pub fn find_last_lines_offset(
+    file: &File,
+    num_lines: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    if printer.record_start().is_some() {
+        return find_last_records_offset(file, num_lines, printer);
+    }
+
+    let file_size: u64 = file.metadata()?.len();
+    let mut pos: u64 = file_size;
     let mut line_count: u64 = 0;
-    let line_terminator: u8 = if zero_terminated { b'\0' } else { b'\n' };
+    let delimiter: Delimiter = printer.input_delimiter().clone();
+    let delimiter_len: u64 = delimiter.len() as u64;
 
-    let first_byte = if pos > 0 {
-        let mut buffer = [0; 1];
-        file.read_exact_at(&mut buffer, pos - 1)?;
-        buffer[0]
+    let ends_with_delimiter = if pos >= delimiter_len {
+        let mut buffer = vec![0; delimiter.len()];
+        file.read_exact_at(&mut buffer, pos - delimiter_len)?;
+        delimiter.is_suffix_of(&buffer)
     } else {
-        0
+        false
     };
 
-    if first_byte != line_terminator {
-        line_count += 1; // Account for the first line if it doesn't start with a terminator
+    if !ends_with_delimiter {
+        line_count += 1; // Account for the last line if it isn't terminated
     }
 
     if num_lines == 0 {
-        // Nothing to print
-        return Ok(());
+        return Ok(file_size);
     }
 
-    let mut chunk_buffer: Vec<u8> = vec![0; CHUNK_SIZE as usize];
+    // Each chunk overlaps the next by delimiter_len - 1 bytes,
+    // so a delimiter split across two chunks is still found
+    let overlap_size: u64 = delimiter_len - 1;
+    let mut chunk_buffer: Vec<u8> = vec![0; (CHUNK_SIZE + overlap_size) as usize];
 
     let mut line_offset = 0;
 
@@ -42,17 +79,23 @@ pub fn tail_file(
         // Use saturating_sub to avoid underflow
         pos = pos.saturating_sub(read_size);
 
-        file.read_exact_at(&mut chunk_buffer[..read_size as usize], pos)?;
+        let overlap = overlap_size.min(file_size - pos - read_size);
+        let window_size = (read_size + overlap) as usize;
+        file.read_exact_at(&mut chunk_buffer[..window_size], pos)?;
 
-        let terminators: Vec<usize> =
-            memrchr_iter(line_terminator, &chunk_buffer[..read_size as usize]).collect();
+        // Only delimiters starting in this chunk, the rest were counted already
+        let mut terminators: Vec<usize> = delimiter
+            .find_iter_at(&chunk_buffer[..window_size], pos)
+            .filter(|&idx| idx < read_size as usize)
+            .collect();
+        terminators.reverse();
 
         let lines_needed = num_lines.saturating_sub(line_count) + 1;
 
         if lines_needed <= terminators.len() as u64 {
             // N-th line terminator is inside this chunk
             let idx = terminators[(lines_needed - 1) as usize];
-            line_offset = pos + idx as u64 + 1; // start after terminator
+            line_offset = pos + idx as u64 + delimiter_len; // start after terminator
             break;
         } else {
             // Not in this chunk, just increment line_count
@@ -60,8 +103,149 @@ pub fn tail_file(
         }
     }
 
-    // Write from line_offset to end of file
-    write_out(file, line_offset)?;
+    Ok(line_offset)
+}
 
-    Ok(())
+/// Offset of the start of the N-th record from the end, or 0 if there are fewer
+fn find_last_records_offset(
+    file: &File,
+    num_records: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+    let mut records = ReverseRecords::new(file, file_size, printer)?;
+    let mut record_count: u64 = 0;
+
+    if num_records == 0 {
+        return Ok(file_size);
+    }
+
+    while let Some((record_offset, _)) = records.next_record()? {
+        record_count += 1;
+        if record_count == num_records {
+            return Ok(record_offset);
+        }
+    }
+
+    Ok(0)
+}
+
+/// Offset of the start of the N-th matching record from the end, or 0 if there are fewer
+fn find_last_matches(
+    file: &File,
+    num_lines: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+    let mut records = ReverseRecords::new(file, file_size, printer)?;
+    let mut match_count: u64 = 0;
+
+    while let Some((record_offset, record)) = records.next_record()? {
+        if printer.filter().matches(record) {
+            match_count += 1;
+            if match_count == num_lines {
+                return include_before_context(&mut records, record_offset, printer);
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+/// Step back over the leading context of the first match, stopping short of an earlier match
+fn include_before_context(
+    records: &mut ReverseRecords,
+    match_offset: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut start_offset = match_offset;
+
+    for _ in 0..printer.before_context() {
+        match records.next_record()? {
+            Some((record_offset, record)) if !printer.filter().matches(record) => {
+                start_offset = record_offset;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(start_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtail::test_util::{printer, temp_file};
+
+    #[test]
+    fn last_records_keep_continuation_lines() {
+        let file = temp_file("last-records", b"ERROR a\n  at x\nINFO b\n  at y\n  at z\n");
+        let printer = printer(&["--record-start", "^[A-Z]+ "]);
+
+        assert_eq!(find_last_matches(&file, 1, &printer).unwrap(), 15);
+        assert_eq!(find_last_matches(&file, 2, &printer).unwrap(), 0);
+    }
+
+    #[test]
+    fn last_matches_take_before_context() {
+        let file = temp_file("before-context", b"a\nb\nERR 1\nc\nd\nERR 2\n");
+
+        let one_before = printer(&["--grep", "ERR", "-B", "1"]);
+        assert_eq!(find_last_matches(&file, 1, &one_before).unwrap(), 12);
+        assert_eq!(find_last_matches(&file, 2, &one_before).unwrap(), 2);
+
+        // Context stops short of an earlier match, which isn't counted
+        let three_before = printer(&["--grep", "ERR", "-B", "3"]);
+        assert_eq!(find_last_matches(&file, 1, &three_before).unwrap(), 10);
+        assert_eq!(find_last_matches(&file, 2, &three_before).unwrap(), 0);
+    }
+
+    #[test]
+    fn last_lines_offset_counts_records() {
+        let file = temp_file("last-records-offset", b"ERROR a\n  at x\nINFO b\n  at y\n");
+        let printer = printer(&["--record-start", "^[A-Z]+ "]);
+
+        assert_eq!(find_last_lines_offset(&file, 0, &printer).unwrap(), 29);
+        assert_eq!(find_last_lines_offset(&file, 1, &printer).unwrap(), 15);
+        assert_eq!(find_last_lines_offset(&file, 2, &printer).unwrap(), 0);
+        assert_eq!(find_last_lines_offset(&file, 3, &printer).unwrap(), 0);
+    }
+
+    #[test]
+    fn last_lines_offset() {
+        let file = temp_file("last-lines", b"one\ntwo\nthree\n");
+        let printer = printer(&[]);
+
+        assert_eq!(find_last_lines_offset(&file, 1, &printer).unwrap(), 8);
+        assert_eq!(find_last_lines_offset(&file, 2, &printer).unwrap(), 4);
+        assert_eq!(find_last_lines_offset(&file, 10, &printer).unwrap(), 0);
+    }
+
+    #[test]
+    fn last_line_without_delimiter_counts() {
+        let file = temp_file("unterminated", b"one\ntwo");
+        assert_eq!(find_last_lines_offset(&file, 1, &printer(&[])).unwrap(), 4);
+    }
+
+    #[test]
+    fn delimiter_split_across_chunks() {
+        // A \r\n whose \r ends one chunk and whose \n starts the next
+        let chunk = CHUNK_SIZE as usize;
+        let mut contents = vec![b'a'; chunk - 1];
+        contents.extend_from_slice(b"\r\n");
+        contents.extend_from_slice(&vec![b'b'; chunk]);
+        contents.extend_from_slice(b"\r\nlast\r\n");
+        let file = temp_file("split-crlf", &contents);
+        let printer = printer(&["--delimiter", r"\r\n"]);
+
+        assert_eq!(
+            find_last_lines_offset(&file, 1, &printer).unwrap(),
+            2 * chunk as u64 + 3
+        );
+        assert_eq!(
+            find_last_lines_offset(&file, 2, &printer).unwrap(),
+            chunk as u64 + 1
+        );
+        assert_eq!(find_last_lines_offset(&file, 3, &printer).unwrap(), 0);
+    }
 }
@@ -1,34 +1,65 @@
-use memchr::memchr_iter;
-use std::{
-    fs::File,
-    io::{Read, Seek, SeekFrom},
-};
+use encoding_rs::Encoding;
+use regex::bytes::Regex;
+use std::{fs::File, os::unix::fs::FileExt};
 
-use crate::rtail::{constants::CHUNK_SIZE, write_out};
+use crate::rtail::{
+    Printer, constants::CHUNK_SIZE, delimiter::Delimiter, encoding::decode_line, write_out,
+};
 
 pub fn offset_tail(
     file: &mut File,
     start_line: u64,
-    zero_terminated: bool,
+    printer: &mut Printer,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let start_offset = find_line_offset(file, start_line, printer)?;
+    if printer.record_start().is_none() {
+        printer.seek_line(start_offset, start_line.max(1));
+    }
+
+    write_out(file, start_offset, printer)?;
+
+    Ok(())
+}
+
+/// Offset of the start of line N, counting from 1, or the end of the file if there are fewer
+pub fn find_line_offset(
+    file: &File,
+    start_line: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
     let mut line_count: u64 = 1;
-    let line_terminator: u8 = if zero_terminated { b'\0' } else { b'\n' };
-    let mut buffer = vec![0; CHUNK_SIZE as usize];
+    let delimiter: Delimiter = printer.input_delimiter().clone();
+    let file_size: u64 = file.metadata()?.len();
+    // Each chunk overlaps the next so a delimiter split across two chunks is still found
+    let overlap_size: usize = delimiter.len() - 1;
+    let mut buffer = vec![0; CHUNK_SIZE as usize + overlap_size];
     let mut start_offset: u64 = 0;
+    let mut chunk_pos: u64 = 0;
 
     // start_line 0f 1 or 0 means from the beginning, so no need to seek
-    if start_line > 1 {
-        loop {
-            let bytes_read = file.read(&mut buffer)?;
-            if bytes_read == 0 {
-                break; // EOF
-            }
-
-            let current_pos = file.seek(SeekFrom::Current(0))?;
+    if let Some(record_start) = printer.record_start()
+        && start_line > 1
+    {
+        start_offset = find_record_offset(
+            file,
+            0,
+            start_line,
+            &delimiter,
+            record_start,
+            printer.encoding(),
+        )?;
+    } else if start_line > 1 {
+        while chunk_pos < file_size {
+            let window_size =
+                (CHUNK_SIZE as usize + overlap_size).min((file_size - chunk_pos) as usize);
+            let bytes_read = (CHUNK_SIZE as usize).min(window_size);
+            file.read_exact_at(&mut buffer[..window_size], chunk_pos)?;
 
-            // Collect all terminators in this chunk
-            let terminators: Vec<usize> =
-                memchr_iter(line_terminator, &buffer[..bytes_read]).collect();
+            // Collect all terminators starting in this chunk
+            let terminators: Vec<usize> = delimiter
+                .find_iter_at(&buffer[..window_size], chunk_pos)
+                .filter(|&idx| idx < bytes_read)
+                .collect();
 
             // Check if the N-th line terminator is inside this chunk
             let lines_needed: u64 = start_line.saturating_sub(line_count);
@@ -36,21 +67,170 @@ pub fn offset_tail(
             if lines_needed == 0 {
                 // start_line has already been reached, use current position
                 // This should not happen due to the loop condition, but just in case
-                start_offset = current_pos - bytes_read as u64;
+                start_offset = chunk_pos;
                 break;
             } else if lines_needed <= terminators.len() as u64 {
                 // The N-th line terminator is in this chunk
                 let line_pos = terminators[(lines_needed - 1) as usize]; // zero-based
-                start_offset = current_pos - bytes_read as u64 + line_pos as u64 + 1;
+                start_offset = chunk_pos + (line_pos + delimiter.len()) as u64;
                 break;
             } else {
                 // Not in this chunk, just increment line_count
                 line_count += terminators.len() as u64;
             }
+
+            chunk_pos += bytes_read as u64;
+        }
+
+        if chunk_pos >= file_size {
+            // Fewer lines than requested, so there is nothing to print
+            start_offset = file_size;
         }
     }
 
-    write_out(file, start_offset)?;
+    Ok(start_offset)
+}
 
-    Ok(())
+/// Offset of the first record starting at or after the line at `line_offset`,
+/// so that printing from there doesn't begin with continuation lines
+pub fn next_record_offset(
+    file: &File,
+    line_offset: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    match printer.record_start() {
+        Some(record_start) => find_record_offset(
+            file,
+            line_offset,
+            1,
+            printer.input_delimiter(),
+            record_start,
+            printer.encoding(),
+        ),
+        None => Ok(line_offset),
+    }
+}
+
+/// Offset of the start of record N counting from the line at `from_offset`,
+/// where the lines before the first header of the file form a record
+fn find_record_offset(
+    file: &File,
+    from_offset: u64,
+    start_record: u64,
+    delimiter: &Delimiter,
+    record_start: &Regex,
+    encoding: &'static Encoding,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+    let mut chunk = vec![0; CHUNK_SIZE as usize];
+    // File bytes starting at `buffer_pos`, of which `line_start..` are still unread lines
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut buffer_pos: u64 = from_offset;
+    let mut line_start: usize = 0;
+    let mut search_from: usize = 0;
+    let mut read_pos: u64 = from_offset;
+    let mut record_count: u64 = 0;
+
+    loop {
+        let mut line_end = delimiter
+            .find_at(&buffer[search_from..], buffer_pos + search_from as u64)
+            .map(|idx| search_from + idx);
+
+        if line_end.is_none() {
+            if read_pos < file_size {
+                // Drop the lines already looked at, then read more
+                buffer.drain(..line_start);
+                buffer_pos += line_start as u64;
+                search_from = (buffer.len() + 1).saturating_sub(delimiter.len());
+                line_start = 0;
+
+                let read_size = CHUNK_SIZE.min(file_size - read_pos) as usize;
+                file.read_exact_at(&mut chunk[..read_size], read_pos)?;
+                buffer.extend_from_slice(&chunk[..read_size]);
+                read_pos += read_size as u64;
+                continue;
+            }
+
+            if line_start == buffer.len() {
+                // Fewer records than requested, so there is nothing to print
+                return Ok(file_size);
+            }
+            // The last line of the file has no delimiter
+            line_end = Some(buffer.len());
+        }
+
+        let line_end = line_end.unwrap_or(buffer.len());
+        let line_offset = buffer_pos + line_start as u64;
+
+        if line_offset == 0
+            || record_start.is_match(&decode_line(encoding, &buffer[line_start..line_end]))
+        {
+            record_count += 1;
+            if record_count == start_record {
+                return Ok(line_offset);
+            }
+        }
+
+        line_start = (line_end + delimiter.len()).min(buffer.len());
+        search_from = line_start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtail::test_util::{printer, temp_file};
+
+    #[test]
+    fn records_start_at_headers() {
+        let file = temp_file("records", b"preamble\nERROR a\n  more\nINFO b\n");
+        let printer = printer(&["--record-start", "^[A-Z]+ "]);
+
+        assert_eq!(find_line_offset(&file, 2, &printer).unwrap(), 9);
+        assert_eq!(find_line_offset(&file, 3, &printer).unwrap(), 24);
+        assert_eq!(find_line_offset(&file, 4, &printer).unwrap(), 31);
+    }
+
+    #[test]
+    fn next_record_skips_continuation_lines() {
+        let file = temp_file("next-record", b"ERROR a\n  more\n  again\nINFO b\n  more\n");
+        let records = printer(&["--record-start", "^[A-Z]+ "]);
+
+        assert_eq!(next_record_offset(&file, 0, &records).unwrap(), 0);
+        assert_eq!(next_record_offset(&file, 8, &records).unwrap(), 23);
+        assert_eq!(next_record_offset(&file, 23, &records).unwrap(), 23);
+        assert_eq!(next_record_offset(&file, 30, &records).unwrap(), 37);
+        assert_eq!(next_record_offset(&file, 8, &printer(&[])).unwrap(), 8);
+    }
+
+    #[test]
+    fn line_offset() {
+        let file = temp_file("line-offset", b"one\ntwo\nthree\n");
+        let printer = printer(&[]);
+
+        assert_eq!(find_line_offset(&file, 1, &printer).unwrap(), 0);
+        assert_eq!(find_line_offset(&file, 2, &printer).unwrap(), 4);
+        assert_eq!(find_line_offset(&file, 3, &printer).unwrap(), 8);
+        assert_eq!(find_line_offset(&file, 4, &printer).unwrap(), 14);
+        assert_eq!(find_line_offset(&file, 10, &printer).unwrap(), 14);
+    }
+
+    #[test]
+    fn delimiter_split_across_chunks() {
+        // A "---" that starts two bytes before the end of the first chunk
+        let chunk = CHUNK_SIZE as usize;
+        let mut contents = vec![b'a'; chunk - 2];
+        contents.extend_from_slice(b"---second---third");
+        let file = temp_file("split-dashes", &contents);
+        let printer = printer(&["--delimiter", "---"]);
+
+        assert_eq!(
+            find_line_offset(&file, 2, &printer).unwrap(),
+            chunk as u64 + 1
+        );
+        assert_eq!(
+            find_line_offset(&file, 3, &printer).unwrap(),
+            chunk as u64 + 10
+        );
+    }
 }
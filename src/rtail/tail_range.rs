@@ -0,0 +1,229 @@
+use std::{fs::File, os::unix::fs::FileExt};
+
+use crate::rtail::{
+    Printer,
+    constants::CHUNK_SIZE,
+    tail_file::find_last_lines_offset,
+    tail_file_by_offset::{find_line_offset, next_record_offset},
+    write_range,
+};
+
+/// Inclusive range of lines or bytes, counting from 1, where negative
+/// positions count back from the end and a missing end point is open
+#[derive(Clone, Copy, Debug)]
+pub struct Range {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl Range {
+    /// Parse `START:END`, such as `1000:2000`, `-50:` or `:-10`
+    pub fn parse(input: &str) -> Result<Range, String> {
+        let (start, end) = input
+            .split_once(':')
+            .ok_or_else(|| format!("expected START:END, got '{}'", input))?;
+
+        Ok(Range {
+            start: parse_position(start)?,
+            end: parse_position(end)?,
+        })
+    }
+}
+
+fn parse_position(input: &str) -> Result<Option<i64>, String> {
+    if input.is_empty() {
+        return Ok(None);
+    }
+
+    match input.parse::<i64>() {
+        Ok(0) => Err("positions start at 1, or -1 for the last one".to_string()),
+        Ok(n) => Ok(Some(n)),
+        Err(e) => Err(format!("invalid position '{}': {}", input, e)),
+    }
+}
+
+/// Where to start reading: a byte offset or a percentage of the file size
+#[derive(Clone, Copy, Debug)]
+pub enum StartPoint {
+    Offset(u64),
+    Percent(f64),
+}
+
+impl StartPoint {
+    /// Parse a byte offset such as `1048576`, or a percentage such as `75%`
+    pub fn parse(input: &str) -> Result<StartPoint, String> {
+        match input.strip_suffix('%') {
+            Some(percent) => match percent.parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(StartPoint::Percent(p)),
+                _ => Err(format!("invalid percentage '{}'", input)),
+            },
+            None => input
+                .parse::<u64>()
+                .map(StartPoint::Offset)
+                .map_err(|e| format!("invalid offset '{}': {}", input, e)),
+        }
+    }
+}
+
+/// Print the lines in `range`
+pub fn tail_line_range(
+    file: &mut File,
+    range: Range,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+
+    let start_offset: u64 = match range.start {
+        None => 0,
+        Some(n) if n > 0 => find_line_offset(file, n as u64, printer)?,
+        Some(n) => find_last_lines_offset(file, n.unsigned_abs(), printer)?,
+    };
+
+    // The end is inclusive, so stop where the line after it starts
+    let end_offset: u64 = match range.end {
+        None => file_size,
+        Some(n) if n > 0 => find_line_offset(file, n as u64 + 1, printer)?,
+        Some(n) => find_last_lines_offset(file, n.unsigned_abs() - 1, printer)?,
+    };
+
+    if let Some(n) = range.start
+        && n > 0
+        && printer.record_start().is_none()
+    {
+        printer.seek_line(start_offset, n as u64);
+    }
+
+    write_range(file, start_offset, end_offset.max(start_offset), printer)?;
+
+    Ok(())
+}
+
+/// Print the bytes in `range`
+pub fn tail_byte_range(
+    file: &mut File,
+    range: Range,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+
+    let start_offset: u64 = match range.start {
+        None => 0,
+        Some(n) if n > 0 => (n as u64 - 1).min(file_size),
+        Some(n) => file_size.saturating_sub(n.unsigned_abs()),
+    };
+
+    let end_offset: u64 = match range.end {
+        None => file_size,
+        Some(n) if n > 0 => (n as u64).min(file_size),
+        Some(n) => file_size.saturating_sub(n.unsigned_abs() - 1),
+    };
+
+    write_range(file, start_offset, end_offset.max(start_offset), printer)?;
+
+    Ok(())
+}
+
+/// Print from `start`, moved forward to the start of the next record
+pub fn tail_from(
+    file: &mut File,
+    start: StartPoint,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+
+    let offset: u64 = match start {
+        StartPoint::Offset(offset) => offset.min(file_size),
+        StartPoint::Percent(percent) => (file_size as f64 * percent / 100.0) as u64,
+    };
+
+    let start_offset: u64 = match printer.record_size() {
+        Some(record_size) => offset.next_multiple_of(record_size).min(file_size),
+        None => next_record_offset(file, next_line_offset(file, offset, printer)?, printer)?,
+    };
+
+    write_range(file, start_offset, file_size, printer)?;
+
+    Ok(())
+}
+
+/// Offset of the first line starting at or after `offset`
+pub fn next_line_offset(
+    file: &File,
+    offset: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+    let delimiter = printer.input_delimiter();
+    let overlap_size: u64 = delimiter.len() as u64 - 1;
+    let mut buffer = vec![0; (CHUNK_SIZE + overlap_size) as usize];
+
+    if offset == 0 {
+        return Ok(0);
+    }
+
+    // Start early enough to see a delimiter that ends right at `offset`
+    let mut chunk_pos: u64 = offset.saturating_sub(delimiter.len() as u64);
+
+    while chunk_pos < file_size {
+        let window_size = (CHUNK_SIZE + overlap_size).min(file_size - chunk_pos) as usize;
+        file.read_exact_at(&mut buffer[..window_size], chunk_pos)?;
+
+        let line_start = delimiter
+            .find_iter_at(&buffer[..window_size], chunk_pos)
+            .map(|idx| chunk_pos + (idx + delimiter.len()) as u64)
+            .find(|&line_start| line_start >= offset);
+
+        if let Some(line_start) = line_start {
+            return Ok(line_start);
+        }
+
+        chunk_pos += CHUNK_SIZE;
+    }
+
+    Ok(file_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(range: Range) -> (Option<i64>, Option<i64>) {
+        (range.start, range.end)
+    }
+
+    #[test]
+    fn parse_range() {
+        assert_eq!(
+            positions(Range::parse("1000:2000").unwrap()),
+            (Some(1000), Some(2000))
+        );
+        assert_eq!(
+            positions(Range::parse("-50:-10").unwrap()),
+            (Some(-50), Some(-10))
+        );
+        assert_eq!(positions(Range::parse("-50:").unwrap()), (Some(-50), None));
+        assert_eq!(positions(Range::parse(":10").unwrap()), (None, Some(10)));
+        assert_eq!(positions(Range::parse(":").unwrap()), (None, None));
+    }
+
+    #[test]
+    fn parse_range_rejects_bad_input() {
+        assert!(Range::parse("10").is_err());
+        assert!(Range::parse("0:10").is_err());
+        assert!(Range::parse("1:x").is_err());
+        assert!(Range::parse("1:2:3").is_err());
+    }
+
+    #[test]
+    fn parse_start_point() {
+        assert!(matches!(
+            StartPoint::parse("1048576"),
+            Ok(StartPoint::Offset(1048576))
+        ));
+        assert!(matches!(StartPoint::parse("75%"), Ok(StartPoint::Percent(p)) if p == 75.0));
+        assert!(matches!(StartPoint::parse("0%"), Ok(StartPoint::Percent(p)) if p == 0.0));
+        assert!(StartPoint::parse("101%").is_err());
+        assert!(StartPoint::parse("-1").is_err());
+        assert!(StartPoint::parse("x%").is_err());
+    }
+}
@@ -0,0 +1,26 @@
+use std::fs::File;
+
+use crate::rtail::{Printer, write_range};
+
+pub fn tail_records(
+    file: &mut File,
+    num_records: u64,
+    is_plus: bool,
+    record_size: u64,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A trailing incomplete record is left out
+    let record_count: u64 = file.metadata()?.len() / record_size;
+    let end_pos: u64 = record_count * record_size;
+
+    let start_record: u64 = if is_plus {
+        // Records are numbered from 1, and +0 means the same as +1
+        num_records.saturating_sub(1).min(record_count)
+    } else {
+        record_count.saturating_sub(num_records)
+    };
+
+    write_range(file, start_record * record_size, end_pos, printer)?;
+
+    Ok(())
+}
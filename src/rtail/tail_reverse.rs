@@ -0,0 +1,67 @@
+use std::fs::File;
+
+use crate::rtail::{
+    Printer,
+    encoding::{bom_len, decode_line},
+    reverse_lines::ReverseLines,
+    write_std_out::copy_range,
+};
+
+/// Print the lines between `start_offset` and the end of the file newest first,
+/// stopping after `num_lines` of them if given
+pub fn tail_reverse(
+    file: &mut File,
+    start_offset: u64,
+    num_lines: Option<u64>,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+    let delimiter = printer.input_delimiter().clone();
+    let encoding = printer.encoding();
+    // The header lines were printed already, and the byte order mark would
+    // end up after the last line
+    let start_offset: u64 = start_offset.max(printer.header_end()).max(bom_len(file)?);
+    // Only the start of a long line is held, the printer cuts it while it is streamed
+    let mut lines = ReverseLines::new(
+        file,
+        start_offset,
+        file_size,
+        delimiter.clone(),
+        printer.max_line_length(),
+    )?;
+    let mut line_count: u64 = 0;
+
+    if num_lines == Some(0) {
+        return Ok(());
+    }
+
+    // Each line is written as soon as it is found, so only one chunk is held at a time
+    while let Some((line_offset, line_end, line)) = lines.next_line_with_end()? {
+        printer.seek(line_offset);
+        if line_end - line_offset > line.len() as u64 {
+            // The line was cut by the scanner, so the printer reads it whole from the file
+            copy_range(file, line_offset, line_end, printer)?;
+        } else {
+            printer.write(line)?;
+        }
+        printer.write(delimiter.as_bytes())?;
+
+        if printer.stop_reason().is_some() {
+            break;
+        }
+
+        // Only lines that pass the filters count towards NUM
+        if let Some(num_lines) = num_lines
+            && printer.filter().matches(&decode_line(encoding, line))
+        {
+            line_count += 1;
+            if line_count == num_lines {
+                break;
+            }
+        }
+    }
+
+    printer.finish()?;
+
+    Ok(())
+}
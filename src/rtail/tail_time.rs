@@ -0,0 +1,190 @@
+use chrono::{DateTime, Utc};
+use std::{fs::File, os::unix::fs::FileExt};
+
+use crate::rtail::{
+    Printer, constants::CHUNK_SIZE, delimiter::Delimiter, encoding::decode_line,
+    tail_range::next_line_offset, timestamp::find_timestamp, write_range,
+};
+
+/// Print the records stamped from `since` up to, but not including, `until`
+pub fn tail_time_range(
+    file: &mut File,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+
+    let start_offset: u64 = match since {
+        Some(since) => find_time_offset(file, since, printer.header_end(), printer)?,
+        None => 0,
+    };
+    let end_offset: u64 = match until {
+        Some(until) => find_time_offset(file, until, start_offset, printer)?,
+        None => file_size,
+    };
+
+    write_range(file, start_offset, end_offset.max(start_offset), printer)?;
+
+    Ok(())
+}
+
+/// Offset of the first line stamped at or after `time`, or the end of the file.
+/// Logs are mostly in time order, so a binary search by byte offset narrows it down
+/// to a window that is then scanned line by line.
+fn find_time_offset(
+    file: &File,
+    time: DateTime<Utc>,
+    start_offset: u64,
+    printer: &Printer,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let file_size: u64 = file.metadata()?.len();
+    let mut low: u64 = start_offset;
+    let mut high: u64 = file_size;
+
+    while high - low > CHUNK_SIZE {
+        let mid = low + (high - low) / 2;
+        let line_start = next_line_offset(file, mid, printer)?;
+        let mut lines = ForwardLines::new(file, line_start, high, printer.input_delimiter());
+
+        // Lines without a timestamp, such as stack traces, belong to the one before
+        let mut stamped = None;
+        while let Some((line_start, line)) = lines.next_line()? {
+            if let Some(timestamp) = find_timestamp(&decode_line(printer.encoding(), line)) {
+                stamped = Some((line_start, timestamp.time));
+                break;
+            }
+        }
+
+        match stamped {
+            Some((line_start, stamp)) if stamp < time => low = line_start,
+            _ => high = mid,
+        }
+    }
+
+    // Start the scan a chunk early, so that lines slightly out of order
+    // around the boundary still end up on the right side of it
+    let scan_start = next_line_offset(
+        file,
+        low.saturating_sub(CHUNK_SIZE).max(start_offset),
+        printer,
+    )?;
+    let mut lines = ForwardLines::new(file, scan_start, file_size, printer.input_delimiter());
+
+    while let Some((line_start, line)) = lines.next_line()? {
+        if find_timestamp(&decode_line(printer.encoding(), line))
+            .is_some_and(|timestamp| timestamp.time >= time)
+        {
+            return Ok(line_start);
+        }
+    }
+
+    Ok(file_size)
+}
+
+/// Reads the lines of a file from a line start towards `end_offset`, in chunks
+struct ForwardLines<'a> {
+    file: &'a File,
+    delimiter: &'a Delimiter,
+    // File bytes starting at `buffer_pos`
+    buffer: Vec<u8>,
+    buffer_pos: u64,
+    // Start of the part of `buffer` not handed out yet
+    start: usize,
+    end_offset: u64,
+}
+
+impl<'a> ForwardLines<'a> {
+    fn new(
+        file: &'a File,
+        start_offset: u64,
+        end_offset: u64,
+        delimiter: &'a Delimiter,
+    ) -> ForwardLines<'a> {
+        ForwardLines {
+            file,
+            delimiter,
+            buffer: Vec::new(),
+            buffer_pos: start_offset,
+            start: 0,
+            end_offset,
+        }
+    }
+
+    /// The next line and the offset it starts at, without its delimiter
+    fn next_line(&mut self) -> std::io::Result<Option<(u64, &[u8])>> {
+        loop {
+            let line_start = self.buffer_pos + self.start as u64;
+            if line_start >= self.end_offset {
+                return Ok(None);
+            }
+
+            let unread = &self.buffer[self.start..];
+            if let Some(idx) = self.delimiter.find_at(unread, line_start) {
+                let start = self.start;
+                self.start += idx + self.delimiter.len();
+                return Ok(Some((line_start, &self.buffer[start..start + idx])));
+            }
+
+            let read_pos = self.buffer_pos + self.buffer.len() as u64;
+            let read_size = CHUNK_SIZE.min(self.end_offset.saturating_sub(read_pos)) as usize;
+
+            if read_size == 0 {
+                // The last line, without a delimiter
+                let start = self.start;
+                self.start = self.buffer.len();
+                return Ok(Some((line_start, &self.buffer[start..])));
+            }
+
+            // Keep only the partial line, then read more
+            self.buffer.drain(..self.start);
+            self.buffer_pos += self.start as u64;
+            self.start = 0;
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + read_size, 0);
+            self.file
+                .read_exact_at(&mut self.buffer[filled..], read_pos)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtail::test_util::{printer, temp_file};
+
+    const START: i64 = 1714521600;
+    const LINE_LEN: u64 = 22;
+
+    fn time(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    #[test]
+    fn binary_search_finds_first_line_at_or_after() {
+        // Long enough for the search to narrow down several chunks
+        let contents: String = (0..10000)
+            .map(|idx| format!("{} line {:05}\n", START + idx * 2, idx))
+            .collect();
+        let file = temp_file("time-search", contents.as_bytes());
+        let printer = printer(&[]);
+
+        let offset = |secs| find_time_offset(&file, time(secs), 0, &printer).unwrap();
+        assert_eq!(offset(START), 0);
+        assert_eq!(offset(START + 14000), 7000 * LINE_LEN);
+        // Between two stamps, the later one
+        assert_eq!(offset(START + 14001), 7001 * LINE_LEN);
+        assert_eq!(offset(START + 20000), 10000 * LINE_LEN);
+    }
+
+    #[test]
+    fn unstamped_lines_belong_to_the_record_before() {
+        let contents = format!("{} a\n  trace\n{} b\n  trace\n", START, START + 10);
+        let file = temp_file("time-trace", contents.as_bytes());
+
+        assert_eq!(
+            find_time_offset(&file, time(START + 5), 0, &printer(&[])).unwrap(),
+            21
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use regex::bytes::Regex;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Why output stopped, each with its own exit status
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    PatternMatched,
+    MaxLines,
+    Timeout,
+    IdleTimeout,
+    ProcessExited,
+    Interrupted,
+}
+
+impl StopReason {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            StopReason::PatternMatched => 0,
+            StopReason::MaxLines => 3,
+            StopReason::IdleTimeout => 4,
+            StopReason::Timeout => 124,
+            StopReason::ProcessExited => 0,
+            StopReason::Interrupted => 0,
+        }
+    }
+}
+
+/// Conditions checked against every line that is output
+pub struct StopConditions {
+    pub until: Option<Regex>,
+    pub max_lines: Option<u64>,
+    lines_output: u64,
+}
+
+impl StopConditions {
+    pub fn new(until: Option<Regex>, max_lines: Option<u64>) -> StopConditions {
+        StopConditions {
+            until,
+            max_lines,
+            lines_output: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.until.is_some() || self.max_lines.is_some()
+    }
+
+    /// Record that `line` was output, returns the reason to stop if any
+    pub fn check(&mut self, line: &[u8]) -> Option<StopReason> {
+        self.lines_output += 1;
+
+        if self.until.as_ref().is_some_and(|re| re.is_match(line)) {
+            return Some(StopReason::PatternMatched);
+        }
+        if self.max_lines.is_some_and(|max| self.lines_output >= max) {
+            return Some(StopReason::MaxLines);
+        }
+
+        None
+    }
+}
+
+/// Deadlines that end a follow. Clones share when data last arrived, so the idle
+/// time of files followed together is measured across all of them.
+#[derive(Clone)]
+pub struct Timeouts {
+    pub overall: Option<Duration>,
+    pub idle: Option<Duration>,
+    started: Instant,
+    last_data: Arc<Mutex<Instant>>,
+}
+
+impl Timeouts {
+    pub fn new(overall: Option<Duration>, idle: Option<Duration>) -> Timeouts {
+        let now = Instant::now();
+
+        Timeouts {
+            overall,
+            idle,
+            started: now,
+            last_data: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn data_received(&mut self) {
+        *self.last_data.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_data
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .elapsed()
+    }
+
+    /// How long to wait for the next event, None if there is no deadline
+    pub fn time_left(&self) -> Option<Duration> {
+        let overall_left = self
+            .overall
+            .map(|overall| overall.saturating_sub(self.started.elapsed()));
+        let idle_left = self.idle.map(|idle| idle.saturating_sub(self.idle_for()));
+
+        match (overall_left, idle_left) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn expired(&self) -> Option<StopReason> {
+        if self
+            .overall
+            .is_some_and(|overall| self.started.elapsed() >= overall)
+        {
+            return Some(StopReason::Timeout);
+        }
+        if self.idle.is_some_and(|idle| self.idle_for() >= idle) {
+            return Some(StopReason::IdleTimeout);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_on_matching_line() {
+        let mut conditions = StopConditions::new(Some(Regex::new("ready").unwrap()), None);
+
+        assert!(conditions.is_active());
+        assert_eq!(conditions.check(b"starting"), None);
+        assert_eq!(
+            conditions.check(b"server ready"),
+            Some(StopReason::PatternMatched)
+        );
+    }
+
+    #[test]
+    fn stops_after_max_lines() {
+        let mut conditions = StopConditions::new(None, Some(2));
+
+        assert_eq!(conditions.check(b"one"), None);
+        assert_eq!(conditions.check(b"two"), Some(StopReason::MaxLines));
+    }
+
+    #[test]
+    fn match_wins_over_max_lines() {
+        let mut conditions = StopConditions::new(Some(Regex::new("two").unwrap()), Some(2));
+
+        assert_eq!(conditions.check(b"one"), None);
+        assert_eq!(conditions.check(b"two"), Some(StopReason::PatternMatched));
+    }
+
+    #[test]
+    fn inactive_without_conditions() {
+        let mut conditions = StopConditions::new(None, None);
+
+        assert!(!conditions.is_active());
+        assert_eq!(conditions.check(b"line"), None);
+    }
+
+    #[test]
+    fn timeouts_expire() {
+        let timeouts = Timeouts::new(Some(Duration::ZERO), None);
+        assert_eq!(timeouts.expired(), Some(StopReason::Timeout));
+
+        let timeouts = Timeouts::new(None, Some(Duration::ZERO));
+        assert_eq!(timeouts.expired(), Some(StopReason::IdleTimeout));
+
+        let timeouts = Timeouts::new(Some(Duration::from_secs(60)), Some(Duration::from_secs(5)));
+        assert_eq!(timeouts.expired(), None);
+        assert!(
+            timeouts
+                .time_left()
+                .is_some_and(|left| left <= Duration::from_secs(5))
+        );
+        assert_eq!(Timeouts::new(None, None).time_left(), None);
+    }
+
+    #[test]
+    fn clones_share_idle_time() {
+        let mut quiet = Timeouts::new(None, Some(Duration::from_millis(50)));
+        let mut busy = quiet.clone();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(quiet.expired(), Some(StopReason::IdleTimeout));
+
+        busy.data_received();
+        assert_eq!(quiet.expired(), None);
+        quiet.data_received();
+        assert_eq!(busy.expired(), None);
+    }
+}
@@ -0,0 +1,36 @@
+use clap::Parser;
+use std::{
+    fs::{self, File},
+    io::Write,
+    sync::mpsc,
+};
+
+use crate::rtail::{Args, Printer};
+
+/// A file holding `contents`, already unlinked so nothing is left behind
+pub fn temp_file(name: &str, contents: &[u8]) -> File {
+    let path = std::env::temp_dir().join(format!("rtail-{}-{}", std::process::id(), name));
+    File::create(&path).unwrap().write_all(contents).unwrap();
+    let file = File::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    file
+}
+
+/// A printer set up as by the command line `rtail ARGS...`
+pub fn printer(args: &[&str]) -> Printer {
+    Printer::new(&Args::parse_from([&["rtail"], args].concat())).unwrap()
+}
+
+/// What `printer` outputs for `input`, taken from the merge channel rather than stdout
+pub fn output(printer: &mut Printer, input: &[u8]) -> String {
+    let (merge_tx, merge_rx) = mpsc::channel();
+    printer.set_merge(merge_tx);
+    printer.write(input).unwrap();
+    printer.finish().unwrap();
+
+    let bytes: Vec<u8> = merge_rx
+        .try_iter()
+        .flat_map(|record| record.bytes)
+        .collect();
+    String::from_utf8(bytes).unwrap()
+}
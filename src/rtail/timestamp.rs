@@ -0,0 +1,438 @@
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    format::{Item, StrftimeItems},
+};
+use chrono_tz::Tz;
+use regex::bytes::{Captures, Regex};
+use std::{ops::Range, sync::LazyLock};
+
+use crate::rtail::parse_duration;
+
+// Timestamps are only looked for this far into a line
+const SEARCH_LEN: usize = 256;
+
+// In each pattern, group 1 is the timestamp itself
+static ISO_8601: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"((\d{4})-(\d{2})-(\d{2})[T ](\d{2}):(\d{2}):(\d{2})(?:[.,](\d{1,9}))?(?: ?(Z|[+-]\d{2}:?\d{2}))?)",
+    )
+    .expect("valid ISO 8601 regex")
+});
+// nginx and Apache, such as [10/Oct/2000:13:55:36 -0700]
+static COMMON_LOG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\[((\d{2})/([A-Z][a-z]{2})/(\d{4}):(\d{2}):(\d{2}):(\d{2}) ([+-]\d{4}))\]")
+        .expect("valid common log regex")
+});
+// Syslog has no year, such as Oct 11 22:14:15
+static SYSLOG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:<\d{1,3}>)?(([A-Z][a-z]{2}) ([ \d]\d) (\d{2}):(\d{2}):(\d{2}))")
+        .expect("valid syslog regex")
+});
+// Seconds or milliseconds since 1970, at the start of a line or as a JSON time value
+static EPOCH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?:^\[?|"(?:ts|time|timestamp|@timestamp)"\s*:\s*)(\d{13}|\d{10}(?:\.\d{1,9})?)\b"#,
+    )
+    .expect("valid epoch regex")
+});
+
+// What --tz rewrites timestamps as without --time-format
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+type TimeParser = fn(&Captures) -> Option<DateTime<Utc>>;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A timestamp found in a line, and the bytes it takes up
+#[derive(Clone, Debug)]
+pub struct Timestamp {
+    pub time: DateTime<Utc>,
+    pub span: Range<usize>,
+}
+
+/// The first ISO 8601, nginx/Apache, syslog or epoch timestamp near the start of `line`;
+/// times without a zone are taken as local time
+pub fn find_timestamp(line: &[u8]) -> Option<Timestamp> {
+    let line = &line[..line.len().min(SEARCH_LEN)];
+
+    let parsers: [(&Regex, TimeParser); 4] = [
+        (&ISO_8601, parse_iso_8601),
+        (&COMMON_LOG, parse_common_log),
+        (&SYSLOG, parse_syslog),
+        (&EPOCH, parse_epoch),
+    ];
+
+    parsers
+        .iter()
+        .filter_map(|(regex, parse)| {
+            let captures = regex.captures(line)?;
+            Some(Timestamp {
+                time: parse(&captures)?,
+                span: captures.get(1)?.range(),
+            })
+        })
+        .min_by_key(|timestamp| timestamp.span.start)
+}
+
+/// Rewrites the timestamp of each record in one zone and format, for --tz and --time-format
+#[derive(Clone, Debug)]
+pub struct Retimer {
+    // Local time without --tz
+    zone: Option<Tz>,
+    format: String,
+}
+
+impl Retimer {
+    pub fn new(zone: Option<Tz>, format: Option<String>) -> Retimer {
+        Retimer {
+            zone,
+            format: format.unwrap_or_else(|| DEFAULT_TIME_FORMAT.to_string()),
+        }
+    }
+
+    /// `record` with its timestamp rewritten, or None if it has none
+    pub fn rewrite(&self, record: &[u8]) -> Option<Vec<u8>> {
+        let timestamp = find_timestamp(record)?;
+        let text = match self.zone {
+            Some(zone) => timestamp.time.with_timezone(&zone).format(&self.format),
+            None => timestamp.time.with_timezone(&Local).format(&self.format),
+        }
+        .to_string();
+
+        let before = &record[..timestamp.span.start];
+        // An epoch JSON value becomes a string
+        let quote = before.trim_ascii_end().ends_with(b":");
+
+        let mut rewritten = Vec::with_capacity(record.len() + text.len() + 2);
+        rewritten.extend_from_slice(before);
+        if quote {
+            rewritten.push(b'"');
+        }
+        rewritten.extend_from_slice(text.as_bytes());
+        if quote {
+            rewritten.push(b'"');
+        }
+        rewritten.extend_from_slice(&record[timestamp.span.end..]);
+
+        Some(rewritten)
+    }
+}
+
+/// Parse a time zone name such as `Europe/Berlin` or `UTC`
+pub fn parse_zone(input: &str) -> Result<Tz, String> {
+    input.parse().map_err(|_| {
+        format!(
+            "unknown time zone '{}', expected a name such as Europe/Berlin",
+            input
+        )
+    })
+}
+
+/// Check a strftime format such as `%H:%M:%S%.3f`
+pub fn parse_time_format(input: &str) -> Result<String, String> {
+    if StrftimeItems::new(input).any(|item| item == Item::Error) {
+        return Err(format!("invalid time format '{}'", input));
+    }
+
+    Ok(input.to_string())
+}
+
+/// Parse a point in time given on the command line: a duration ago such as `15m`, a time
+/// today such as `14:05`, a date and time such as `2024-05-01 14:05:00`, or `@EPOCH`
+pub fn parse_time(input: &str) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+
+    if let Some(epoch) = input.strip_prefix('@') {
+        let secs: f64 = epoch
+            .parse()
+            .map_err(|_| format!("invalid epoch time '{}'", input))?;
+        return DateTime::from_timestamp_millis((secs * 1000.0) as i64)
+            .ok_or_else(|| format!("epoch time '{}' is out of range", input));
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(input, format) {
+            return local_to_utc(naive).ok_or_else(|| format!("'{}' doesn't exist locally", input));
+        }
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return local_to_utc(date.and_time(NaiveTime::MIN))
+            .ok_or_else(|| format!("'{}' doesn't exist locally", input));
+    }
+
+    for format in ["%H:%M:%S", "%H:%M"] {
+        if let Ok(time) = NaiveTime::parse_from_str(input, format) {
+            let today = Local::now().date_naive();
+            return local_to_utc(today.and_time(time))
+                .ok_or_else(|| format!("'{}' doesn't exist locally today", input));
+        }
+    }
+
+    match parse_duration(input) {
+        Ok(ago) => Ok(Utc::now() - ago),
+        Err(_) => Err(format!(
+            "invalid time '{}', expected a duration such as 15m, HH:MM, YYYY-MM-DD HH:MM:SS or @EPOCH",
+            input
+        )),
+    }
+}
+
+fn local_to_utc(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn number(captures: &Captures, idx: usize) -> Option<u32> {
+    std::str::from_utf8(captures.get(idx)?.as_bytes())
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn text<'a>(captures: &'a Captures, idx: usize) -> Option<&'a str> {
+    std::str::from_utf8(captures.get(idx)?.as_bytes()).ok()
+}
+
+fn month(name: &str) -> Option<u32> {
+    MONTHS
+        .iter()
+        .position(|month| *month == name)
+        .map(|idx| idx as u32 + 1)
+}
+
+/// Offsets such as `+02:00`, `-0700` or `Z`
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    if offset == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+
+    let sign = if offset.starts_with('-') { -1 } else { 1 };
+    let digits: String = offset[1..].chars().filter(|c| *c != ':').collect();
+    let hours: i32 = digits.get(..2)?.parse().ok()?;
+    let minutes: i32 = digits.get(2..4)?.parse().ok()?;
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+fn with_offset(naive: NaiveDateTime, offset: Option<FixedOffset>) -> Option<DateTime<Utc>> {
+    match offset {
+        Some(offset) => offset
+            .from_local_datetime(&naive)
+            .single()
+            .map(|time| time.with_timezone(&Utc)),
+        None => local_to_utc(naive),
+    }
+}
+
+fn parse_iso_8601(captures: &Captures) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::from_ymd_opt(
+        number(captures, 2)? as i32,
+        number(captures, 3)?,
+        number(captures, 4)?,
+    )?;
+    // Pad the fraction out to nanoseconds
+    let nanos = match text(captures, 8) {
+        Some(fraction) => format!("{:0<9}", fraction).parse().ok()?,
+        None => 0,
+    };
+    let time = NaiveTime::from_hms_nano_opt(
+        number(captures, 5)?,
+        number(captures, 6)?,
+        number(captures, 7)?,
+        nanos,
+    )?;
+    let offset = match text(captures, 9) {
+        Some(offset) => Some(parse_offset(offset)?),
+        None => None,
+    };
+
+    with_offset(date.and_time(time), offset)
+}
+
+fn parse_common_log(captures: &Captures) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::from_ymd_opt(
+        number(captures, 4)? as i32,
+        month(text(captures, 3)?)?,
+        number(captures, 2)?,
+    )?;
+    let time = NaiveTime::from_hms_opt(
+        number(captures, 5)?,
+        number(captures, 6)?,
+        number(captures, 7)?,
+    )?;
+
+    with_offset(date.and_time(time), Some(parse_offset(text(captures, 8)?)?))
+}
+
+fn parse_syslog(captures: &Captures) -> Option<DateTime<Utc>> {
+    let month = month(text(captures, 2)?)?;
+    let time = NaiveTime::from_hms_opt(
+        number(captures, 4)?,
+        number(captures, 5)?,
+        number(captures, 6)?,
+    )?;
+    let now = Local::now();
+
+    // Assume this year, unless that puts it well in the future, as in December's lines read in January
+    let this_year = NaiveDate::from_ymd_opt(now.year(), month, number(captures, 3)?)?;
+    let local = local_to_utc(this_year.and_time(time))?;
+    if local <= now.with_timezone(&Utc) + chrono::Duration::days(1) {
+        return Some(local);
+    }
+
+    let last_year = NaiveDate::from_ymd_opt(now.year() - 1, month, number(captures, 3)?)?;
+    local_to_utc(last_year.and_time(time))
+}
+
+fn parse_epoch(captures: &Captures) -> Option<DateTime<Utc>> {
+    let value = text(captures, 1)?;
+
+    if value.len() == 13 && !value.contains('.') {
+        return DateTime::from_timestamp_millis(value.parse().ok()?);
+    }
+
+    let secs: f64 = value.parse().ok()?;
+    DateTime::from_timestamp_millis((secs * 1000.0).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(input: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(input)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn found(line: &str) -> Option<(DateTime<Utc>, Range<usize>)> {
+        find_timestamp(line.as_bytes()).map(|timestamp| (timestamp.time, timestamp.span))
+    }
+
+    #[test]
+    fn finds_iso_8601() {
+        assert_eq!(
+            found("2024-05-01T14:05:00Z INFO up"),
+            Some((utc("2024-05-01T14:05:00Z"), 0..20))
+        );
+        assert_eq!(
+            found("level=info ts=2024-05-01 14:05:00.250+02:00 msg=up"),
+            Some((utc("2024-05-01T12:05:00.25Z"), 14..43))
+        );
+        assert_eq!(
+            found("2024-05-01T14:05:00,123456789 -0700"),
+            Some((utc("2024-05-01T21:05:00.123456789Z"), 0..35))
+        );
+    }
+
+    #[test]
+    fn finds_common_log() {
+        let line = r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200"#;
+        assert_eq!(found(line), Some((utc("2000-10-10T20:55:36Z"), 15..41)));
+    }
+
+    #[test]
+    fn finds_epoch() {
+        assert_eq!(
+            found("1714572300 started"),
+            Some((utc("2024-05-01T14:05:00Z"), 0..10))
+        );
+        assert_eq!(
+            found(r#"{"level":"info","ts":1714572300250}"#),
+            Some((utc("2024-05-01T14:05:00.25Z"), 21..34))
+        );
+        assert_eq!(
+            found(r#"{"time": 1714572300.5, "msg": "up"}"#),
+            Some((utc("2024-05-01T14:05:00.5Z"), 9..21))
+        );
+    }
+
+    #[test]
+    fn earliest_timestamp_in_line_wins() {
+        let line = "[10/Oct/2000:13:55:36 +0000] replayed 2024-05-01T14:05:00Z";
+        assert_eq!(
+            found(line).map(|(time, _)| time),
+            Some(utc("2000-10-10T13:55:36Z"))
+        );
+    }
+
+    #[test]
+    fn ignores_lines_without_timestamps() {
+        assert!(found("no time here").is_none());
+        assert!(found("request took 1714572300 ns").is_none());
+        assert!(found("2024-13-01T14:05:00Z invalid month").is_none());
+        assert!(found(&format!("{}2024-05-01T14:05:00Z", " ".repeat(SEARCH_LEN))).is_none());
+    }
+
+    #[test]
+    fn parse_absolute_times() {
+        assert_eq!(parse_time("@1714572300"), Ok(utc("2024-05-01T14:05:00Z")));
+        assert_eq!(
+            parse_time("@1714572300.5"),
+            Ok(utc("2024-05-01T14:05:00.5Z"))
+        );
+        assert_eq!(
+            parse_time("2024-05-01T14:05:00+02:00"),
+            Ok(utc("2024-05-01T12:05:00Z"))
+        );
+    }
+
+    #[test]
+    fn parse_times_ago() {
+        let before = Utc::now();
+        let time = parse_time("15m").unwrap();
+        let expected = before - chrono::Duration::minutes(15);
+
+        assert!(time >= expected && time - expected < chrono::Duration::seconds(5));
+    }
+
+    #[test]
+    fn parse_rejects_bad_times() {
+        assert!(parse_time("yesterday").is_err());
+        assert!(parse_time("@soon").is_err());
+        assert!(parse_time("25:00").is_err());
+    }
+
+    #[test]
+    fn rewrite_in_zone_and_format() {
+        let retimer = Retimer::new(Some(chrono_tz::Asia::Tokyo), None);
+        assert_eq!(
+            retimer.rewrite(b"2024-05-01T14:05:00Z INFO up"),
+            Some(b"2024-05-01T23:05:00.000+09:00 INFO up".to_vec())
+        );
+
+        let retimer = Retimer::new(Some(chrono_tz::UTC), Some("%H:%M:%S".to_string()));
+        assert_eq!(
+            retimer.rewrite(b"[10/Oct/2000:13:55:36 -0700] GET /"),
+            Some(b"[20:55:36] GET /".to_vec())
+        );
+        assert_eq!(retimer.rewrite(b"no time here"), None);
+    }
+
+    #[test]
+    fn rewrite_quotes_json_epoch() {
+        let retimer = Retimer::new(Some(chrono_tz::UTC), Some("%H:%M:%S".to_string()));
+        assert_eq!(
+            retimer.rewrite(br#"{"ts":1714572300,"msg":"up"}"#),
+            Some(br#"{"ts":"14:05:00","msg":"up"}"#.to_vec())
+        );
+    }
+
+    #[test]
+    fn parse_zone_and_format() {
+        assert_eq!(parse_zone("Europe/Berlin"), Ok(chrono_tz::Europe::Berlin));
+        assert!(parse_zone("Mars/Olympus").is_err());
+        assert!(parse_time_format("%H:%M:%S%.3f").is_ok());
+        assert!(parse_time_format("%Q").is_err());
+    }
+}
@@ -1,31 +1,80 @@
 use std::{
     fs::File,
-    io::{Read, Seek, SeekFrom, Stdout, Write},
+    io::{Read, Seek, SeekFrom},
+    os::unix::fs::FileExt,
 };
 
-pub fn write_out(file: &mut File, start_offset: u64) -> Result<(), Box<dyn std::error::Error>> {
+use crate::rtail::{Printer, tail_file_by_offset::find_line_offset};
+
+pub fn write_out(
+    file: &mut File,
+    start_offset: u64,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
     let file_size: u64 = file.metadata()?.len();
-    file.seek(SeekFrom::Start(start_offset))?;
-    let stdout: Stdout = std::io::stdout();
-    let chunk_size: usize = 1024 * 8; // Read in 32KB chunks
 
-    // Calculate total bytes to read
-    // Saturating subtraction to avoid underflow
-    let mut bytes_remaining: u64 = file_size.saturating_sub(start_offset);
-    let mut stdout_lock = stdout.lock();
+    write_range(file, start_offset, file_size, printer)
+}
+
+/// Write the bytes from `start_offset` up to `end_offset`
+pub fn write_range(
+    file: &mut File,
+    start_offset: u64,
+    end_offset: u64,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The header lines were printed already
+    let start_offset: u64 = start_offset.max(printer.header_end());
+
+    printer.seek(start_offset);
+    copy_range(file, start_offset, end_offset, printer)?;
+    printer.finish()?;
+
+    Ok(())
+}
+
+/// Write the bytes from `start_offset` up to `end_offset` a chunk at a time,
+/// leaving the output open for more
+pub fn copy_range(
+    file: &File,
+    start_offset: u64,
+    end_offset: u64,
+    printer: &mut Printer,
+) -> std::io::Result<()> {
+    let chunk_size: usize = 1024 * 8; // Read in 8KB chunks
+    let mut pos: u64 = start_offset;
+    let mut chunk_buffer = vec![0; chunk_size];
 
-    // Read and print the rest of the file from the start_offset
-    while bytes_remaining > 0 {
-        let read_size = chunk_size.min(bytes_remaining as usize);
-        let mut chunk_buffer = vec![0; read_size];
+    while pos < end_offset {
+        let read_size = chunk_size.min((end_offset - pos) as usize);
 
         // Read exact number of bytes into the buffer
-        file.read_exact(&mut chunk_buffer)?;
+        file.read_exact_at(&mut chunk_buffer[..read_size], pos)?;
 
-        // Print the chunk as UTF-8, replacing invalid sequences
-        stdout_lock.write_all(&chunk_buffer)?;
-        bytes_remaining -= read_size as u64;
+        printer.write(&chunk_buffer[..read_size])?;
+        pos += read_size as u64;
     }
 
     Ok(())
 }
+
+/// Print the header lines of the file, if any were asked for, ahead of the tail
+pub fn write_header(
+    file: &mut File,
+    printer: &mut Printer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if printer.header_lines() == 0 {
+        return Ok(());
+    }
+
+    let header_end: u64 = find_line_offset(file, printer.header_lines() + 1, printer)?;
+    let mut header = vec![0; header_end as usize];
+
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    printer.seek(0);
+    printer.write(&header)?;
+    printer.set_header_end(header_end);
+
+    Ok(())
+}